@@ -1,6 +1,11 @@
 pub mod achievements;
+pub mod cloudstorage;
 pub mod components;
+pub mod friends;
 pub mod leaderboards;
+pub mod library;
 pub mod overlay;
+pub mod presence;
+pub mod products;
 pub mod stats;
 pub mod users;
@@ -64,6 +64,7 @@ pub async fn fetch_achievements(
     token_store: &TokenStorage,
     client_id: &str,
     user_id: &str,
+    language: &str,
     reqwest_client: &Client,
 ) -> Result<(Vec<Achievement>, String), MessageHandlingError> {
     let token = {
@@ -80,7 +81,7 @@ pub async fn fetch_achievements(
     let response = reqwest_client
         .get(url)
         .bearer_auth(token.access_token)
-        .header("X-Gog-Lc", crate::LOCALE.as_str())
+        .header("X-Gog-Lc", language)
         .send()
         .await
         .map_err(MessageHandlingError::network)?;
@@ -0,0 +1,122 @@
+use crate::api::handlers::error::MessageHandlingError;
+use crate::constants::TokenStorage;
+use reqwest::Client;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteFile {
+    /// Path relative to the client storage, starts with the save location name
+    pub name: String,
+    /// md5 of the stored data
+    pub hash: String,
+    pub last_modified: String,
+    pub bytes: u64,
+}
+
+async fn get_token(
+    token_store: &TokenStorage,
+    client_id: &str,
+) -> Result<String, MessageHandlingError> {
+    let lock = token_store.lock().await;
+    Ok(lock
+        .get(client_id)
+        .ok_or(MessageHandlingError::unauthorized())?
+        .access_token
+        .clone())
+}
+
+fn storage_url(client_id: &str, user_id: &str) -> String {
+    format!("https://cloudstorage.gog.com/v1/{}/{}", user_id, client_id)
+}
+
+pub async fn list_files(
+    token_store: &TokenStorage,
+    client_id: &str,
+    user_id: &str,
+    reqwest_client: &Client,
+) -> Result<Vec<RemoteFile>, MessageHandlingError> {
+    let token = get_token(token_store, client_id).await?;
+    let response = reqwest_client
+        .get(storage_url(client_id, user_id))
+        .bearer_auth(token)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+
+    response
+        .json::<Vec<RemoteFile>>()
+        .await
+        .map_err(MessageHandlingError::network)
+}
+
+pub async fn download_file(
+    token_store: &TokenStorage,
+    client_id: &str,
+    user_id: &str,
+    name: &str,
+    reqwest_client: &Client,
+) -> Result<Vec<u8>, MessageHandlingError> {
+    let token = get_token(token_store, client_id).await?;
+    let response = reqwest_client
+        .get(format!("{}/{}", storage_url(client_id, user_id), name))
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+
+    let data = response
+        .bytes()
+        .await
+        .map_err(MessageHandlingError::network)?;
+    Ok(data.to_vec())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_file(
+    token_store: &TokenStorage,
+    client_id: &str,
+    user_id: &str,
+    name: &str,
+    data: Vec<u8>,
+    hash: &str,
+    last_modified: &str,
+    reqwest_client: &Client,
+) -> Result<(), MessageHandlingError> {
+    let token = get_token(token_store, client_id).await?;
+    reqwest_client
+        .put(format!("{}/{}", storage_url(client_id, user_id), name))
+        .bearer_auth(token)
+        .header("Etag", hash)
+        .header("X-Object-Meta-LocalLastModified", last_modified)
+        .body(data)
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+    Ok(())
+}
+
+pub async fn delete_file(
+    token_store: &TokenStorage,
+    client_id: &str,
+    user_id: &str,
+    name: &str,
+    reqwest_client: &Client,
+) -> Result<(), MessageHandlingError> {
+    let token = get_token(token_store, client_id).await?;
+    reqwest_client
+        .delete(format!("{}/{}", storage_url(client_id, user_id), name))
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+    Ok(())
+}
@@ -1,21 +1,32 @@
-use async_zip::base::read::mem::ZipFileReader;
+use async_zip::tokio::read::fs::ZipFileReader;
 use derive_getters::Getters;
 use futures::StreamExt;
-use futures_util::io;
+use futures_util::AsyncReadExt;
+use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::{fmt::Display, path::PathBuf, time::Instant};
 use tokio::fs;
-use tokio_util::compat::TokioAsyncWriteCompatExt;
+use tokio::io::AsyncWriteExt;
 
 use reqwest::Client;
 
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
 pub enum Platform {
     Windows,
+    #[value(name = "osx")]
     Mac,
 }
 
+impl Platform {
+    pub const ALL: [Platform; 2] = [Platform::Windows, Platform::Mac];
+}
+
 impl Display for Platform {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -54,17 +65,78 @@ struct ComponentSymlink {
     target: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
 pub enum Component {
     Peer,
     Overlay,
     Web,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct ComponentManifestLocal {
+impl Component {
+    pub const ALL: [Component; 3] = [Component::Peer, Component::Overlay, Component::Web];
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ComponentManifestLocal {
     pub time: i64,
     pub version: String,
+    /// Updates are skipped while the component is pinned to this version
+    #[serde(default)]
+    pub pinned: Option<String>,
+    /// Installed files and symlinks, relative to the redist directory
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Hashes of installed files from the component manifest, unchanged files are not downloaded
+    #[serde(default)]
+    pub hashes: BTreeMap<String, String>,
+}
+
+fn manifest_path(dest_path: &Path, platform: Platform, component: Component) -> PathBuf {
+    dest_path.join(format!(".{}-{}.toml", component, platform))
+}
+
+fn backup_path(dest_path: &Path, platform: Platform, component: Component) -> PathBuf {
+    dest_path
+        .join(".backup")
+        .join(format!("{}-{}", component, platform))
+}
+
+fn backup_sibling(backup_path: &Path, suffix: &str) -> PathBuf {
+    let mut name = backup_path.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+    backup_path.with_file_name(name)
+}
+
+// Backup of the running install, it replaces the previous backup once the install succeeds
+fn pending_backup_path(backup_path: &Path) -> PathBuf {
+    backup_sibling(backup_path, ".new")
+}
+
+// Exists while installed files are swapped, lists files the install adds
+fn install_marker_path(backup_path: &Path) -> PathBuf {
+    backup_sibling(backup_path, ".installing")
+}
+
+async fn read_manifest(path: &Path) -> Option<ComponentManifestLocal> {
+    let manifest_str = fs::read_to_string(path).await.ok()?;
+    match toml::from_str(&manifest_str) {
+        Ok(manifest) => Some(manifest),
+        Err(err) => {
+            log::warn!("Invalid component manifest {:?} {err}", path);
+            None
+        }
+    }
+}
+
+async fn write_manifest(
+    path: &Path,
+    manifest: &ComponentManifestLocal,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let data = toml::to_string(manifest).expect("Failed to serialize local manifest");
+    let temp_path = path.with_extension("toml.tmp");
+    fs::write(&temp_path, data).await?;
+    fs::rename(&temp_path, path).await?;
+    Ok(())
 }
 
 impl Display for Component {
@@ -77,31 +149,307 @@ impl Display for Component {
     }
 }
 
-pub async fn get_component(
-    reqwest_client: &Client,
-    dest_path: PathBuf,
-    platform: Platform,
-    component: Component,
+/// Where component files are installed from
+#[derive(Debug, Clone)]
+pub enum ComponentSource {
+    /// GOG content delivery
+    Remote,
+    /// Directory or zip archive with `files-<platform>.json` and the zipped files it references,
+    /// optionally in a `<component>` subdirectory e.g `desktop-galaxy-peer/files-windows.json`
+    Local(PathBuf),
+}
+
+impl ComponentSource {
+    /// Source configured with `runtime.component_source`
+    pub fn from_config() -> Self {
+        match &crate::CONFIG.get().runtime.component_source {
+            Some(path) => Self::Local(path.clone()),
+            None => Self::Remote,
+        }
+    }
+}
+
+/// State of a component download passed to the progress callback
+#[derive(Debug, Clone)]
+pub struct DownloadProgress {
+    pub component: Component,
+    pub platform: Platform,
+    /// Size of files extracted so far
+    pub bytes: u64,
+    pub total_bytes: u64,
+    pub files: usize,
+    pub total_files: usize,
+}
+
+pub type ProgressCallback<'a> = &'a (dyn Fn(&DownloadProgress) + Send + Sync);
+
+/// Progress callback writing to the log
+pub fn log_progress(progress: &DownloadProgress) {
+    if progress.bytes == 0 {
+        log::info!(
+            "Downloading component {} - total download size {:.2} MiB",
+            progress.component,
+            progress.total_bytes as f32 / 1024.0 / 1024.0
+        );
+        return;
+    }
+    log::info!(
+        "[{:?}] {:.2} / {:.2} ({}/{} files)",
+        progress.component,
+        progress.bytes as f32 / 1024.0 / 1024.0,
+        progress.total_bytes as f32 / 1024.0 / 1024.0,
+        progress.files,
+        progress.total_files
+    );
+}
+
+// Progress is shared by the download tasks, callback is called at most once a second
+struct ProgressTracker<'a> {
+    callback: ProgressCallback<'a>,
+    state: Mutex<(DownloadProgress, Instant)>,
+}
+
+impl<'a> ProgressTracker<'a> {
+    fn new(callback: ProgressCallback<'a>, progress: DownloadProgress) -> Self {
+        callback(&progress);
+        Self {
+            callback,
+            state: Mutex::new((progress, Instant::now())),
+        }
+    }
+
+    fn update(&self, bytes: u64, files: usize) {
+        let progress = {
+            let mut state = self.state.lock().unwrap();
+            let (progress, last_report) = &mut *state;
+            progress.bytes += bytes;
+            progress.files += files;
+            if last_report.elapsed() < Duration::from_secs(1) {
+                return;
+            }
+            *last_report = Instant::now();
+            progress.clone()
+        };
+        (self.callback)(&progress);
+    }
+
+    fn finish(&self) {
+        let progress = self.state.lock().unwrap().0.clone();
+        (self.callback)(&progress);
+    }
+}
+
+// Copy reader to a new file, chunks are passed to inspect before being written
+async fn copy_to_file<R: futures::AsyncRead + Unpin>(
+    mut reader: R,
+    path: &Path,
+    mut inspect: impl FnMut(&[u8]),
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let manifest_path = dest_path.join(format!(".{}-{}.toml", component, platform));
-    let local_manifest: ComponentManifestLocal = match fs::read_to_string(&manifest_path).await {
-        Ok(manifest_str) => toml::from_str(&manifest_str).unwrap_or_default(),
-        Err(err) => {
-            log::debug!("Failed to read component manifest {err:?}");
-            ComponentManifestLocal::default()
+    let mut output = fs::File::create(path).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        inspect(&buffer[..read]);
+        output.write_all(&buffer[..read]).await?;
+    }
+    output.flush().await?;
+    Ok(())
+}
+
+enum Resources<'a> {
+    Remote {
+        reqwest_client: &'a Client,
+        base_uri: String,
+    },
+    Directory(PathBuf),
+    Archive {
+        zip: ZipFileReader,
+        prefix: String,
+    },
+}
+
+impl Resources<'_> {
+    async fn read(
+        &self,
+        resource: &str,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Self::Remote {
+                reqwest_client,
+                base_uri,
+            } => {
+                let url = format!("{}/{}", base_uri, resource);
+                let response = reqwest_client.get(url).send().await?.error_for_status()?;
+                Ok(response.bytes().await?.to_vec())
+            }
+            Self::Directory(dir) => Ok(fs::read(dir.join(resource)).await?),
+            Self::Archive { zip, prefix } => {
+                let name = format!("{prefix}{resource}");
+                let index = zip
+                    .file()
+                    .entries()
+                    .iter()
+                    .position(|entry| entry.filename().as_str().ok() == Some(name.as_str()))
+                    .ok_or(format!("{name} is missing in the archive"))?;
+                let mut reader = zip.reader_with_entry(index).await?;
+                let mut data = Vec::new();
+                reader.read_to_end_checked(&mut data).await?;
+                Ok(data)
+            }
+        }
+    }
+
+    /// Path of the zipped resource on disk, downloaded and archived resources are written to
+    /// temp_path first
+    async fn fetch(
+        &self,
+        resource: &str,
+        temp_path: &Path,
+    ) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Self::Remote {
+                reqwest_client,
+                base_uri,
+            } => {
+                let url = format!("{}/{}", base_uri, resource);
+                let response = reqwest_client.get(url).send().await?.error_for_status()?;
+                let mut output = fs::File::create(temp_path).await?;
+                let mut stream = response.bytes_stream();
+                while let Some(chunk) = stream.next().await {
+                    output.write_all(&chunk?).await?;
+                }
+                output.flush().await?;
+                Ok(temp_path.to_path_buf())
+            }
+            Self::Directory(dir) => Ok(dir.join(resource)),
+            Self::Archive { zip, prefix } => {
+                let name = format!("{prefix}{resource}");
+                let index = zip
+                    .file()
+                    .entries()
+                    .iter()
+                    .position(|entry| entry.filename().as_str().ok() == Some(name.as_str()))
+                    .ok_or(format!("{name} is missing in the archive"))?;
+                let reader = zip.reader_with_entry(index).await?;
+                copy_to_file(reader, temp_path, |_| {}).await?;
+                Ok(temp_path.to_path_buf())
+            }
         }
-    };
-    if local_manifest.time + (24 * 3600) > chrono::Utc::now().timestamp() {
-        return Ok(());
     }
-    log::debug!("Checking for peer updates");
+}
+
+async fn fetch_remote_manifest<'a>(
+    reqwest_client: &'a Client,
+    platform: Platform,
+    component: Component,
+) -> Result<(ComponentManifest, Resources<'a>), Box<dyn std::error::Error + Send + Sync>> {
     let url = format!(
         "https://cfg.gog.com/{}/7/master/files-{}.json",
         component, platform
     );
-
     let manifest_res = reqwest_client.get(url).send().await?;
     let manifest: ComponentManifest = manifest_res.json().await?;
+    let resources = Resources::Remote {
+        reqwest_client,
+        base_uri: manifest.base_uri().clone(),
+    };
+    Ok((manifest, resources))
+}
+
+async fn read_local_manifest(
+    path: &Path,
+    platform: Platform,
+    component: Component,
+) -> Result<(ComponentManifest, Resources<'static>), Box<dyn std::error::Error + Send + Sync>> {
+    let manifest_name = format!("files-{}.json", platform);
+    if path.is_dir() {
+        for dir in [path.join(component.to_string()), path.to_path_buf()] {
+            let Ok(data) = fs::read(dir.join(&manifest_name)).await else {
+                continue;
+            };
+            let manifest = serde_json::from_slice(&data)?;
+            return Ok((manifest, Resources::Directory(dir)));
+        }
+    } else {
+        let zip = ZipFileReader::new(path).await?;
+        let prefix = [format!("{}/", component), String::new()]
+            .into_iter()
+            .find(|prefix| {
+                let name = format!("{prefix}{manifest_name}");
+                zip.file()
+                    .entries()
+                    .iter()
+                    .any(|entry| entry.filename().as_str().ok() == Some(name.as_str()))
+            });
+        if let Some(prefix) = prefix {
+            let resources = Resources::Archive { zip, prefix };
+            let manifest = serde_json::from_slice(&resources.read(&manifest_name).await?)?;
+            return Ok((manifest, resources));
+        }
+    }
+    Err(format!("{manifest_name} for {component} not found in {:?}", path).into())
+}
+
+pub async fn get_component(
+    reqwest_client: &Client,
+    dest_path: PathBuf,
+    platform: Platform,
+    component: Component,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let source = ComponentSource::from_config();
+    update_component(
+        reqwest_client,
+        &source,
+        dest_path,
+        platform,
+        component,
+        false,
+        &log_progress,
+    )
+    .await
+}
+
+/// Install or update the component from source, with `force` the check interval is ignored
+pub async fn update_component(
+    reqwest_client: &Client,
+    source: &ComponentSource,
+    dest_path: PathBuf,
+    platform: Platform,
+    component: Component,
+    force: bool,
+    progress: ProgressCallback<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let manifest_path = manifest_path(&dest_path, platform, component);
+    let backup_path = backup_path(&dest_path, platform, component);
+    recover_install(&dest_path, &backup_path, &manifest_path).await?;
+    let local_manifest = read_manifest(&manifest_path).await.unwrap_or_default();
+    if let Some(pinned) = &local_manifest.pinned {
+        log::info!("{component} for {platform} is pinned to {pinned}, not updating");
+        return Ok(());
+    }
+    let check_interval: i64 = crate::CONFIG
+        .get()
+        .runtime
+        .component_check_interval
+        .try_into()
+        .unwrap_or(i64::MAX);
+    // Checking local source is cheap, it's done every time
+    if !force
+        && matches!(source, ComponentSource::Remote)
+        && local_manifest.time.saturating_add(check_interval) > chrono::Utc::now().timestamp()
+    {
+        return Ok(());
+    }
+    log::debug!("Checking for {component} updates");
+    let (manifest, resources) = match source {
+        ComponentSource::Remote => {
+            fetch_remote_manifest(reqwest_client, platform, component).await?
+        }
+        ComponentSource::Local(path) => read_local_manifest(path, platform, component).await?,
+    };
 
     if dest_path.exists() {
         if local_manifest.version == manifest.version && !manifest.force_update {
@@ -111,81 +459,570 @@ pub async fn get_component(
         fs::create_dir_all(&dest_path).await?;
     }
 
-    let files_to_dl: Vec<&ComponentFile> = manifest
+    let component_files: Vec<&ComponentFile> = manifest
         .files()
         .iter()
         .filter(|file| !matches!(component, Component::Web) || file.path().starts_with("web"))
         .collect();
+    let mut files_to_dl = Vec::with_capacity(component_files.len());
+    for file in component_files.iter().copied() {
+        let unchanged = local_manifest.hashes.get(file.path()).map(String::as_str)
+            == Some(file.expected_hash())
+            && fs::symlink_metadata(dest_path.join(file.path()))
+                .await
+                .is_ok();
+        if !unchanged {
+            files_to_dl.push(file);
+        }
+    }
+    #[cfg(unix)]
+    let symlinks = manifest.symlinks().as_slice();
+    #[cfg(not(unix))]
+    let symlinks: &[ComponentSymlink] = &[];
+    let mut files: Vec<String> = component_files
+        .iter()
+        .map(|file| file.path().clone())
+        .collect();
+    files.extend(symlinks.iter().map(|symlink| symlink.path().clone()));
+    let new_files: HashSet<&str> = files.iter().map(String::as_str).collect();
+    let removed: Vec<&str> = local_manifest
+        .files
+        .iter()
+        .map(String::as_str)
+        .filter(|path| !new_files.contains(path))
+        .collect();
+    let mut changed_symlinks = Vec::with_capacity(symlinks.len());
+    for symlink in symlinks {
+        let current = fs::read_link(dest_path.join(symlink.path())).await.ok();
+        if current.as_deref() != Some(Path::new(symlink.target())) {
+            changed_symlinks.push(symlink);
+        }
+    }
+    log::debug!(
+        "{component} update: {} of {} files changed, {} removed",
+        files_to_dl.len(),
+        component_files.len(),
+        removed.len()
+    );
 
-    let total_size: u32 = files_to_dl.iter().map(|file| file.size).sum();
-    let mut tasks = Vec::with_capacity(files_to_dl.len());
-    log::info!(
-        "Downloading component {} - total download size {:.2} MiB",
-        component,
-        (total_size as f32) / 1024.0 / 1024.0
+    let hashes = component_files
+        .iter()
+        .map(|file| (file.path().clone(), file.expected_hash().to_owned()))
+        .collect();
+    let new_manifest = ComponentManifestLocal {
+        version: manifest.version().clone(),
+        time: chrono::Utc::now().timestamp(),
+        pinned: None,
+        files,
+        hashes,
+    };
+    // Nothing to swap, backup of the previous version stays as it is
+    if files_to_dl.is_empty() && removed.is_empty() && changed_symlinks.is_empty() {
+        log::info!("{component} files are up to date");
+        return write_manifest(&manifest_path, &new_manifest).await;
+    }
+
+    let total_size: u64 = files_to_dl.iter().map(|file| file.size as u64).sum();
+    let progress = ProgressTracker::new(
+        progress,
+        DownloadProgress {
+            component,
+            platform,
+            bytes: 0,
+            total_bytes: total_size,
+            files: 0,
+            total_files: files_to_dl.len(),
+        },
     );
-    // Download
-    for file in files_to_dl.into_iter() {
-        let url = format!("{}/{}", manifest.base_uri(), file.resource());
-        let reqwest_client = reqwest_client.clone();
-        let file_path = dest_path.join(file.path());
+    let install_name = format!("{}-{}", component, platform);
+    // Files are downloaded next to the destination, so moving them in place is a rename
+    let staging_path = dest_path.join(".staging").join(&install_name);
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path).await?;
+    }
+    // Zipped files are kept on disk until they are extracted
+    let download_path = staging_path.join(".download");
+    fs::create_dir_all(&download_path).await?;
+    let mut tasks = Vec::with_capacity(files_to_dl.len());
+    for (index, file) in files_to_dl.iter().copied().enumerate() {
+        let resources = &resources;
+        let progress = &progress;
+        let temp_path = download_path.join(index.to_string());
+        let file_path = staging_path.join(file.path());
         let parent = file_path.parent();
         if let Some(parent) = parent {
             fs::create_dir_all(parent).await?;
         }
         tasks.push(async move {
-            let response = reqwest_client.get(url).send().await?;
-            let data = response.bytes().await?;
-
-            let zip = ZipFileReader::new(data.to_vec()).await?;
+            let zip_path = resources.fetch(file.resource(), &temp_path).await?;
 
-            let mut reader = zip.reader_with_entry(0).await?;
-            let file_handle = fs::File::create(&file_path).await?;
-            io::copy(&mut reader, &mut file_handle.compat_write()).await?;
+            let zip = ZipFileReader::new(&zip_path).await?;
+            let reader = zip.reader_with_entry(0).await?;
             #[cfg(unix)]
-            if let Some(permissions) = reader.entry().unix_permissions() {
+            let permissions = reader.entry().unix_permissions();
+            let mut hasher = file.hasher();
+            copy_to_file(reader, &file_path, |chunk| {
+                hasher.update(chunk);
+                progress.update(chunk.len() as u64, 0);
+            })
+            .await?;
+            if zip_path == temp_path {
+                fs::remove_file(&temp_path).await?;
+            }
+            file.check(hasher)?;
+            #[cfg(unix)]
+            if let Some(permissions) = permissions {
                 use std::{fs::Permissions, os::unix::fs::PermissionsExt};
                 let permissions = Permissions::from_mode(permissions as u32);
                 fs::set_permissions(file_path, permissions).await?;
             }
-            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(file.size)
+            progress.update(0, 1);
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
         });
     }
 
-    let mut pending_tasks = futures::stream::iter(tasks).buffer_unordered(4);
-
-    let mut total_dl = 0;
-    let mut instant = Instant::now();
+    let jobs = crate::CONFIG.get().runtime.component_download_jobs;
+    let mut pending_tasks = futures::stream::iter(tasks).buffer_unordered(jobs as usize);
     while let Some(res) = pending_tasks.next().await {
-        total_dl += res?;
-        if instant.elapsed() > Duration::from_secs(1) {
-            log::info!(
-                "[{:?}] {:.2} / {:.2}",
-                component,
-                total_dl as f32 / 1024.0 / 1024.0,
-                total_size as f32 / 1024.0 / 1024.0
-            );
-            instant = Instant::now();
+        if let Err(err) = res {
+            drop(pending_tasks);
+            remove_staging(&staging_path).await;
+            return Err(err);
         }
     }
-    log::info!(
-        "[{:?}] {:.2} / {:.2}",
-        component,
-        total_dl as f32 / 1024.0 / 1024.0,
-        total_size as f32 / 1024.0 / 1024.0
+    progress.finish();
+
+    let paths: Vec<&str> = files_to_dl
+        .iter()
+        .map(|file| file.path().as_str())
+        .collect();
+    let installed = install_staged(
+        &dest_path,
+        &staging_path,
+        &backup_path,
+        &paths,
+        &changed_symlinks,
+        &removed,
+        &manifest_path,
+        &new_manifest,
+    )
+    .await;
+    remove_staging(&staging_path).await;
+    installed?;
+
+    Ok(())
+}
+
+enum FileHasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl FileHasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+}
+
+impl ComponentFile {
+    fn expected_hash(&self) -> &str {
+        if self.sha256.is_empty() {
+            &self.hash
+        } else {
+            &self.sha256
+        }
+    }
+
+    fn hasher(&self) -> FileHasher {
+        if self.sha256.is_empty() {
+            FileHasher::Md5(Md5::new())
+        } else {
+            FileHasher::Sha256(Sha256::new())
+        }
+    }
+
+    /// Check extracted file against hashes from the manifest
+    fn check(&self, hasher: FileHasher) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let expected = self.expected_hash();
+        let actual = match hasher {
+            FileHasher::Md5(hasher) => format!("{:x}", hasher.finalize()),
+            FileHasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+        };
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(format!(
+                "{} is corrupted, expected hash {} got {}",
+                self.path, expected, actual
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// Replace installed files with the staged ones and write manifest, files being replaced or
+// removed are kept in backup_path once everything is installed. On failure everything that
+// was already swapped is put back, when the process dies midway it's done by recover_install
+#[allow(clippy::too_many_arguments)]
+async fn install_staged(
+    dest_path: &Path,
+    staging_path: &Path,
+    backup_path: &Path,
+    files: &[&str],
+    symlinks: &[&ComponentSymlink],
+    removed: &[&str],
+    manifest_path: &Path,
+    manifest: &ComponentManifestLocal,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let pending_path = pending_backup_path(backup_path);
+    if pending_path.exists() {
+        fs::remove_dir_all(&pending_path).await?;
+    }
+    fs::create_dir_all(&pending_path).await?;
+    if manifest_path.exists() {
+        fs::copy(manifest_path, pending_path.join("manifest.toml")).await?;
+    }
+    let mut added = Vec::new();
+    for path in files
+        .iter()
+        .copied()
+        .chain(symlinks.iter().map(|symlink| symlink.path().as_str()))
+    {
+        if fs::symlink_metadata(dest_path.join(path)).await.is_err() {
+            added.push(path);
+        }
+    }
+    let marker_path = install_marker_path(backup_path);
+    fs::write(&marker_path, added.join("\n")).await?;
+
+    // Installed path, where its previous version was moved and where the new one came from
+    let mut swapped: Vec<(PathBuf, Option<PathBuf>, Option<PathBuf>)> = Vec::new();
+    let result = async {
+        for path in files {
+            let target = dest_path.join(path);
+            let staged = staging_path.join(path);
+            let backup = move_to_backup(&target, &pending_path.join(path)).await?;
+            swapped.push((target.clone(), backup, Some(staged.clone())));
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::rename(&staged, &target).await?;
+        }
+        #[cfg(unix)]
+        for symlink in symlinks {
+            let target = dest_path.join(symlink.path());
+            let backup = move_to_backup(&target, &pending_path.join(symlink.path())).await?;
+            swapped.push((target.clone(), backup, None));
+            fs::symlink(symlink.target(), &target).await?;
+        }
+        #[cfg(not(unix))]
+        let _ = symlinks;
+        for path in removed {
+            let target = dest_path.join(path);
+            let backup = move_to_backup(&target, &pending_path.join(path)).await?;
+            swapped.push((target, backup, None));
+        }
+        write_manifest(manifest_path, manifest).await
+    }
+    .await;
+
+    if let Err(err) = result {
+        log::warn!("Failed to install component, restoring previous version {err}");
+        for (target, backup, staged) in swapped.into_iter().rev() {
+            // Staged files are kept, restoring a backup puts them back in place
+            match staged {
+                Some(staged) => {
+                    let _ = fs::rename(&target, &staged).await;
+                }
+                None => {
+                    let _ = fs::remove_file(&target).await;
+                }
+            }
+            if let Some(backup) = backup
+                && let Err(err) = fs::rename(&backup, &target).await
+            {
+                log::error!("Failed to restore {:?} {err}", target);
+            }
+        }
+        let _ = fs::remove_dir_all(&pending_path).await;
+        let _ = fs::remove_file(&marker_path).await;
+        return Err(err);
+    }
+
+    if backup_path.exists() {
+        fs::remove_dir_all(backup_path).await?;
+    }
+    fs::rename(&pending_path, backup_path).await?;
+    fs::remove_file(&marker_path).await?;
+    Ok(())
+}
+
+// Put back the previous version when the process died while installing, so installed files
+// match the manifest again
+async fn recover_install(
+    dest_path: &Path,
+    backup_path: &Path,
+    manifest_path: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let marker_path = install_marker_path(backup_path);
+    let Ok(added) = fs::read_to_string(&marker_path).await else {
+        return Ok(());
+    };
+    let pending_path = pending_backup_path(backup_path);
+    // Backup is moved in place after everything was installed
+    if !pending_path.exists() {
+        fs::remove_file(&marker_path).await?;
+        return Ok(());
+    }
+    log::warn!(
+        "Install of {:?} was interrupted, restoring previous version",
+        backup_path.file_name().unwrap_or_default()
     );
+    for path in added.lines() {
+        let _ = fs::remove_file(dest_path.join(path)).await;
+    }
+    let mut had_manifest = false;
+    for path in list_files(&pending_path).await? {
+        let backup = pending_path.join(&path);
+        if path == "manifest.toml" {
+            fs::rename(&backup, manifest_path).await?;
+            had_manifest = true;
+            continue;
+        }
+        let target = dest_path.join(&path);
+        let _ = fs::remove_file(&target).await;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&backup, &target).await?;
+    }
+    fs::remove_dir_all(&pending_path).await?;
+    // Component wasn't installed before
+    if !had_manifest {
+        let _ = fs::remove_file(manifest_path).await;
+    }
+    fs::remove_file(&marker_path).await?;
+    Ok(())
+}
 
-    #[cfg(unix)]
-    for symlink in manifest.symlinks() {
-        fs::symlink(symlink.target(), dest_path.join(symlink.path())).await?;
+async fn move_to_backup(
+    target: &Path,
+    backup: &Path,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error + Send + Sync>> {
+    // symlink_metadata also sees dangling symlinks
+    if fs::symlink_metadata(target).await.is_err() {
+        return Ok(None);
     }
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::rename(target, backup).await?;
+    Ok(Some(backup.to_path_buf()))
+}
 
-    let new_manifest = ComponentManifestLocal {
-        version: manifest.version().clone(),
-        time: chrono::Utc::now().timestamp(),
+/// Component found in the redist directory
+pub struct InstalledComponent {
+    pub component: Component,
+    pub platform: Platform,
+    pub manifest: ComponentManifestLocal,
+    /// Version kept in backup by the last update
+    pub previous_version: Option<String>,
+}
+
+pub async fn installed_components(dest_path: &Path) -> Vec<InstalledComponent> {
+    let mut installed = Vec::new();
+    for component in Component::ALL {
+        for platform in Platform::ALL {
+            let Some(manifest) =
+                read_manifest(&manifest_path(dest_path, platform, component)).await
+            else {
+                continue;
+            };
+            let previous_version =
+                read_manifest(&backup_path(dest_path, platform, component).join("manifest.toml"))
+                    .await
+                    .map(|manifest| manifest.version);
+            installed.push(InstalledComponent {
+                component,
+                platform,
+                manifest,
+                previous_version,
+            });
+        }
+    }
+    installed
+}
+
+/// Delete files of the component, returns false when it's not installed
+pub async fn remove_component(
+    dest_path: &Path,
+    platform: Platform,
+    component: Component,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let manifest_path = manifest_path(dest_path, platform, component);
+    let backup_path = backup_path(dest_path, platform, component);
+    recover_install(dest_path, &backup_path, &manifest_path).await?;
+    let Some(manifest) = read_manifest(&manifest_path).await else {
+        return Ok(false);
     };
-    let data = toml::to_string(&new_manifest).expect("Failed to serialize local manifest");
-    fs::write(manifest_path, data).await?;
+    if manifest.files.is_empty() {
+        log::warn!("{component} was installed by an older version, its files are not tracked");
+    }
+    for file in &manifest.files {
+        let path = dest_path.join(file);
+        if let Err(err) = fs::remove_file(&path).await
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            return Err(err.into());
+        }
+        // Clean up directories left empty
+        for parent in path.ancestors().skip(1) {
+            if parent == dest_path || fs::remove_dir(parent).await.is_err() {
+                break;
+            }
+        }
+    }
+    if backup_path.exists() {
+        fs::remove_dir_all(backup_path).await?;
+    }
+    fs::remove_file(manifest_path).await?;
+    Ok(true)
+}
 
-    Ok(())
+/// Stop updating the component. Pinning to the version from backup restores it first
+pub async fn pin_component(
+    dest_path: &Path,
+    platform: Platform,
+    component: Component,
+    version: Option<String>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let manifest_path = manifest_path(dest_path, platform, component);
+    let backup_path = backup_path(dest_path, platform, component);
+    recover_install(dest_path, &backup_path, &manifest_path).await?;
+    let mut manifest = read_manifest(&manifest_path)
+        .await
+        .ok_or(format!("{component} for {platform} is not installed"))?;
+    let version = version.unwrap_or_else(|| manifest.version.clone());
+
+    if version != manifest.version {
+        let previous = read_manifest(&backup_path.join("manifest.toml")).await;
+        let Some(mut previous) = previous.filter(|previous| previous.version == version) else {
+            return Err(format!(
+                "Version {version} is not available, installed version is {}",
+                manifest.version
+            )
+            .into());
+        };
+        log::info!("Restoring {component} {version}");
+        // Files added by the installed version, older manifests don't list their files
+        let added: Vec<&str> = if previous.files.is_empty() {
+            Vec::new()
+        } else {
+            manifest
+                .files
+                .iter()
+                .map(String::as_str)
+                .filter(|path| !previous.files.iter().any(|file| file == path))
+                .collect()
+        };
+        previous.time = chrono::Utc::now().timestamp();
+        previous.pinned = Some(version.clone());
+        restore_backup(dest_path, &backup_path, &added, &manifest_path, previous).await?;
+        return Ok(version);
+    }
+
+    manifest.pinned = Some(version.clone());
+    write_manifest(&manifest_path, &manifest).await?;
+    Ok(version)
+}
+
+pub async fn unpin_component(
+    dest_path: &Path,
+    platform: Platform,
+    component: Component,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let manifest_path = manifest_path(dest_path, platform, component);
+    let mut manifest = read_manifest(&manifest_path)
+        .await
+        .ok_or(format!("{component} for {platform} is not installed"))?;
+    manifest.pinned = None;
+    write_manifest(&manifest_path, &manifest).await
+}
+
+// Swap files from backup with the installed ones, so the current version becomes the backup
+async fn restore_backup(
+    dest_path: &Path,
+    backup_path: &Path,
+    removed: &[&str],
+    manifest_path: &Path,
+    mut manifest: ComponentManifestLocal,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let staging_path = dest_path
+        .join(".staging")
+        .join(backup_path.file_name().ok_or("Invalid backup directory")?);
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path).await?;
+    }
+    if let Some(parent) = staging_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::rename(backup_path, &staging_path).await?;
+
+    let files: Vec<String> = list_files(&staging_path)
+        .await?
+        .into_iter()
+        .filter(|path| path != "manifest.toml")
+        .collect();
+    let paths: Vec<&str> = files.iter().map(String::as_str).collect();
+    // Older manifests don't list their files
+    if manifest.files.is_empty() {
+        manifest.files = files.clone();
+    }
+    let installed = install_staged(
+        dest_path,
+        &staging_path,
+        backup_path,
+        &paths,
+        &[],
+        removed,
+        manifest_path,
+        &manifest,
+    )
+    .await;
+    if installed.is_err() {
+        // Backup stays available when restoring fails
+        if let Err(err) = fs::rename(&staging_path, backup_path).await {
+            log::error!("Failed to put back backup {:?} {err}", backup_path);
+        }
+    }
+    remove_staging(&staging_path).await;
+    installed
+}
+
+async fn remove_staging(staging_path: &Path) {
+    let _ = fs::remove_dir_all(staging_path).await;
+    // Fails when other component is being installed at the same time
+    if let Some(parent) = staging_path.parent() {
+        let _ = fs::remove_dir(parent).await;
+    }
+}
+
+// Relative paths of files and symlinks in the directory
+async fn list_files(root: &Path) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                files.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    Ok(files)
 }
@@ -0,0 +1,71 @@
+use crate::api::gog::users;
+use crate::api::handlers::error::MessageHandlingError;
+use crate::constants::TokenStorage;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Avatar {
+    pub sdk_img_32: String,
+    pub sdk_img_64: String,
+    pub sdk_img_184: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    #[serde(default)]
+    pub avatar: Avatar,
+}
+
+#[derive(Deserialize, Debug)]
+struct FriendEntry {
+    user: User,
+}
+
+#[derive(Deserialize, Debug)]
+struct FriendsResponse {
+    items: Vec<FriendEntry>,
+}
+
+pub async fn fetch_user(
+    token_store: &TokenStorage,
+    user_id: &str,
+    reqwest_client: &Client,
+) -> Result<User, MessageHandlingError> {
+    let token = users::galaxy_token(token_store, reqwest_client).await?;
+    let response = reqwest_client
+        .get(format!("https://users.gog.com/users/{}", user_id))
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+
+    response.json().await.map_err(MessageHandlingError::network)
+}
+
+pub async fn fetch_friends(
+    token_store: &TokenStorage,
+    user_id: &str,
+    reqwest_client: &Client,
+) -> Result<Vec<User>, MessageHandlingError> {
+    let token = users::galaxy_token(token_store, reqwest_client).await?;
+    let response = reqwest_client
+        .get(format!("https://embed.gog.com/users/{}/friends", user_id))
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+
+    let friends: FriendsResponse = response
+        .json()
+        .await
+        .map_err(MessageHandlingError::network)?;
+    Ok(friends.items.into_iter().map(|entry| entry.user).collect())
+}
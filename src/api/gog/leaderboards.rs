@@ -70,7 +70,7 @@ where
     let response = reqwest_client
         .get(new_url)
         .bearer_auth(token.access_token)
-        .header("X-Gog-Lc", crate::LOCALE.as_str())
+        .header("X-Gog-Lc", context.settings().await.language.as_str())
         .send()
         .await
         .map_err(MessageHandlingError::network)?;
@@ -131,7 +131,7 @@ where
     let response = reqwest_client
         .get(new_url)
         .bearer_auth(token.access_token)
-        .header("X-Gog-Lc", crate::LOCALE.as_str())
+        .header("X-Gog-Lc", context.settings().await.language.as_str())
         .send()
         .await
         .map_err(MessageHandlingError::network)?;
@@ -0,0 +1,33 @@
+use crate::api::gog::users;
+use crate::api::handlers::error::MessageHandlingError;
+use crate::constants::TokenStorage;
+use reqwest::Client;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+struct OwnedGamesResponse {
+    owned: Vec<u64>,
+}
+
+/// Ids of every product the user owns, base games and DLCs
+pub async fn fetch_owned_products(
+    token_store: &TokenStorage,
+    reqwest_client: &Client,
+) -> Result<Vec<u64>, MessageHandlingError> {
+    let token = users::galaxy_token(token_store, reqwest_client).await?;
+
+    let response = reqwest_client
+        .get("https://embed.gog.com/user/data/games")
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+
+    let owned: OwnedGamesResponse = response
+        .json()
+        .await
+        .map_err(MessageHandlingError::network)?;
+    Ok(owned.owned)
+}
@@ -0,0 +1,70 @@
+use crate::api::gog::users;
+use crate::api::handlers::error::MessageHandlingError;
+use crate::constants::TokenStorage;
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Presence {
+    Online,
+    InGame {
+        product_id: String,
+        rich_presence: HashMap<String, String>,
+    },
+    Offline,
+}
+
+#[derive(Serialize)]
+struct StatusRequest<'a> {
+    application_type: &'static str,
+    version: &'static str,
+    presence: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rich_presence: Option<&'a HashMap<String, String>>,
+}
+
+/// Publish user status to GOG presence service so it's visible to friends
+pub async fn set_presence(
+    token_store: &TokenStorage,
+    user_id: &str,
+    presence: &Presence,
+    reqwest_client: &Client,
+) -> Result<(), MessageHandlingError> {
+    let token = users::galaxy_token(token_store, reqwest_client).await?;
+
+    let (status, game_id, rich_presence) = match presence {
+        Presence::Online => ("online", None, None),
+        Presence::InGame {
+            product_id,
+            rich_presence,
+        } => (
+            "online",
+            Some(product_id.as_str()),
+            Some(rich_presence).filter(|values| !values.is_empty()),
+        ),
+        Presence::Offline => ("offline", None, None),
+    };
+    let body = StatusRequest {
+        application_type: "GOG Galaxy",
+        version: "2.0.75.1",
+        presence: status,
+        game_id,
+        rich_presence,
+    };
+
+    reqwest_client
+        .post(format!("https://presence.gog.com/users/{}/status", user_id))
+        .bearer_auth(token)
+        .json(&body)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use crate::api::handlers::error::MessageHandlingError;
+use reqwest::Client;
+use serde_json::Value;
+
+/// Product details from the public products API, image urls are made absolute
+pub async fn fetch_product(
+    product_id: &str,
+    reqwest_client: &Client,
+) -> Result<Value, MessageHandlingError> {
+    let mut data: Value = reqwest_client
+        .get(format!("https://api.gog.com/products/{}", product_id))
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?
+        .json()
+        .await
+        .map_err(MessageHandlingError::network)?;
+
+    if let Some(Value::Object(images)) = data.get_mut("images") {
+        for (_key, url_value) in images.iter_mut() {
+            if let Value::String(url) = url_value
+                && url.starts_with("//")
+            {
+                *url_value = Value::String(format!("https:{}", url));
+            }
+        }
+    }
+    Ok(data)
+}
+
+pub async fn fetch_image(
+    url: &str,
+    reqwest_client: &Client,
+) -> Result<Vec<u8>, MessageHandlingError> {
+    let data = reqwest_client
+        .get(url)
+        .send()
+        .await
+        .map_err(MessageHandlingError::network)?
+        .error_for_status()
+        .map_err(MessageHandlingError::network)?
+        .bytes()
+        .await
+        .map_err(MessageHandlingError::network)?;
+    Ok(data.to_vec())
+}
@@ -1,4 +1,6 @@
+use crate::api::handlers::error::MessageHandlingError;
 use crate::api::structs::Token;
+use crate::constants::{self, TokenStorage};
 use reqwest::{Client, Error};
 use tokio::time;
 
@@ -32,3 +34,41 @@ pub async fn get_token_for(
     let token: Token = result.json().await?;
     Ok(token)
 }
+
+/// Access token of the Galaxy client for GOG services used on behalf of the user.
+/// It's refreshed once it's older than `runtime.token_refresh_age`
+pub async fn galaxy_token(
+    token_store: &TokenStorage,
+    reqwest_client: &Client,
+) -> Result<String, MessageHandlingError> {
+    // Lock is held while refreshing, so concurrent requests don't refresh it again
+    let mut lock = token_store.lock().await;
+    let token = lock
+        .get(constants::GALAXY_CLIENT_ID)
+        .ok_or(MessageHandlingError::unauthorized())?;
+    let refresh_age: i64 = crate::CONFIG
+        .get()
+        .runtime
+        .token_refresh_age
+        .try_into()
+        .unwrap_or(i64::MAX);
+    if chrono::Utc::now().timestamp() - token.obtain_time.timestamp() < refresh_age {
+        return Ok(token.access_token.clone());
+    }
+
+    log::debug!("Refreshing Galaxy credentials");
+    let refresh_token = token.refresh_token.clone();
+    let new_token = get_token_for(
+        constants::GALAXY_CLIENT_ID,
+        constants::GALAXY_CLIENT_SECRET,
+        &refresh_token,
+        reqwest_client,
+        false,
+    )
+    .await
+    .inspect_err(|err| log::warn!("Failed to refresh Galaxy credentials {:?}", err))
+    .map_err(MessageHandlingError::network)?;
+    let access_token = new_token.access_token.clone();
+    lock.insert(String::from(constants::GALAXY_CLIENT_ID), new_token);
+    Ok(access_token)
+}
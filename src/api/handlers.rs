@@ -4,17 +4,26 @@ pub mod error;
 mod overlay_client;
 mod overlay_peer;
 mod overlay_service;
+mod peer_to_peer;
+mod peer_to_server;
+pub mod proxy;
 pub mod utils;
 mod webbroker;
 
 use crate::{constants::TokenStorage, proto::common_utils::ProtoPayload};
 use error::*;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::api::gog;
+use crate::api::gog::presence::Presence;
 use crate::api::notification_pusher::PusherEvent;
 use crate::api::structs::UserInfo;
+use crate::capture::{self, Direction};
 use crate::db;
+use crate::game_info;
+use crate::hooks::{self, HookEvent};
+use crate::overlay::{OverlayRegistry, SessionReceivers};
 use context::HandlerContext;
 use log::{debug, error, info, warn};
 use protobuf::Message;
@@ -23,33 +32,69 @@ use sqlx::Acquire;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
-    sync::broadcast::{Receiver, Sender},
+    sync::broadcast::Receiver,
     time,
 };
 use tokio_util::sync::CancellationToken;
 
 use super::gog::overlay::OverlayPeerMessage;
 
+/// Settings shared by every game connection
+#[derive(Clone, Default, Debug)]
+pub struct ConnectionOptions {
+    /// Directory where traffic captures are written
+    pub record_dir: Option<PathBuf>,
+    pub proxy: Option<proxy::ProxyOptions>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn entry_point(
     mut socket: TcpStream,
     reqwest_client: Client,
     token_store: TokenStorage,
     user_info: Arc<UserInfo>,
     mut topic_receiver: Receiver<PusherEvent>,
-    overlay_event_sender: Sender<(u32, OverlayPeerMessage)>,
+    overlay_registry: OverlayRegistry,
     shutdown_token: CancellationToken,
+    options: ConnectionOptions,
 ) {
     if let Err(err) = socket.readable().await {
         error!("Failed to wait for socket to be readable {}", err);
         let _ = socket.shutdown().await;
         return;
     }
-    let overlay_event_receiver = overlay_event_sender.subscribe();
+    let recorder = capture::recorder_for(options.record_dir.as_deref(), "game").await;
+    let mut upstream = match &options.proxy {
+        Some(proxy_options) => match proxy::Upstream::connect(proxy_options).await {
+            Ok(upstream) => Some(upstream),
+            Err(err) => {
+                error!(
+                    "Failed to connect to upstream {} {err}",
+                    proxy_options.upstream
+                );
+                let _ = socket.shutdown().await;
+                return;
+            }
+        },
+        None => None,
+    };
+    let (overlay_channels, overlay_receivers) = crate::overlay::channels();
+    let SessionReceivers {
+        game: mut overlay_event_receiver,
+        overlay: mut overlay_receiver,
+    } = overlay_receivers;
+    // Lobby events for the game arrive once it identifies itself
+    let (lobby_sender, mut lobby_receiver) = tokio::sync::mpsc::unbounded_channel();
     let context = Arc::new(HandlerContext::new(
         socket,
         token_store,
-        overlay_event_sender,
+        overlay_registry,
+        overlay_channels,
+        lobby_sender,
+        recorder,
     ));
+    // Ends the overlay thread when the game goes away
+    let session_token = shutdown_token.child_token();
     debug!("Awaiting messages");
 
     let shutdown_token_clone = shutdown_token.clone();
@@ -57,7 +102,7 @@ pub async fn entry_point(
     let reqwest_clone = reqwest_client.clone();
     let user_clone = user_info.clone();
     let mut topic_receiver_clone = topic_receiver.resubscribe();
-    let mut overlay_event_receiver_clone = overlay_event_receiver.resubscribe();
+    let mut reload_receiver = crate::reload::subscribe();
     let main_socket = tokio::spawn(async move {
         loop {
             tokio::select! {
@@ -66,12 +111,23 @@ pub async fn entry_point(
                         Ok(h_size) => {
                             let payload = utils::parse_payload(h_size, &mut *context_clone.socket_mut().await).await;
                             let Ok(payload) = payload else { continue };
+                            context_clone.record_incoming(&payload).await;
+
+                            if let Some(upstream) = upstream.as_mut() {
+                                if let Err(err) = upstream.handle_game_frame(&context_clone, user_clone.clone(), &reqwest_clone, payload).await {
+                                    error!("Failed to proxy message {err}");
+                                    break;
+                                }
+                                spawn_session_sync(&context_clone, &reqwest_clone, &user_clone).await;
+                                continue;
+                            }
 
                             match handle_message(&context_clone, user_clone.clone(), &reqwest_clone, payload).await {
                                 Ok(res) => {
-                                    if let Err(err) = context_clone.socket_mut().await.write_all(&res).await {
+                                    if let Err(err) = context_clone.socket_write(&res).await {
                                         error!("Failed to write response {err}");
                                     }
+                                    spawn_session_sync(&context_clone, &reqwest_clone, &user_clone).await;
                                 },
                                 Err(err) => {
                                     match err.kind {
@@ -101,18 +157,47 @@ pub async fn entry_point(
                     }
                 }
 
-                _ = time::sleep(time::Duration::from_secs(10)) => {
+                size_read = read_upstream_u16(&mut upstream) => {
+                    let result = match (size_read, upstream.as_mut()) {
+                        (Ok(h_size), Some(upstream)) => upstream.handle_upstream_frame(&context_clone, h_size).await,
+                        (Err(err), _) => Err(err),
+                        (Ok(_), None) => Ok(()),
+                    };
+                    if let Err(err) = result {
+                        error!("Upstream connection failed {err}");
+                        break;
+                    }
+                }
+
+                Ok(()) = reload_receiver.changed() => {
+                    context_clone.refresh_settings().await;
+                    if let Some(client_id) = context_clone.client_id().await
+                        && let Err(err) = context_clone.load_workarounds(&client_id).await {
+                            debug!("Failed to reload workarounds for {client_id} {err}");
+                        }
+                }
+
+                Some(event) = lobby_receiver.recv() => {
+                    let data = match event {
+                        crate::lobby::LobbyEvent::Packet { .. } => peer_to_peer::encode_packet(&event),
+                        _ => peer_to_server::encode_lobby_event(&event),
+                    };
+                    if let Ok(Some(data)) = data
+                        && let Err(err) = context_clone.socket_write(&data).await {
+                            error!("Failed to send lobby event to the game {err}");
+                        }
+                }
+
+                _ = time::sleep(time::Duration::from_secs(crate::CONFIG.get().runtime.sync_interval)) => {
                     sync_routine(&context_clone, &reqwest_clone, user_clone.clone()).await
                 },
 
-                Ok((pid, data)) = overlay_event_receiver_clone.recv() => {
-                    if context_clone.get_pid().await != pid {continue}
-
+                Some(data) = overlay_event_receiver.recv() => {
                     match data {
                         OverlayPeerMessage::VisibilityChange(visible) => {
                             if let Ok(res) = overlay_peer::encode_visibility_change(visible).await {
                                 log::debug!("Notifying about visibility change to game");
-                                if let Err(err) = context_clone.socket_mut().await.write_all(&res).await {
+                                if let Err(err) = context_clone.socket_write(&res).await {
                                     error!("Failed to notify game of overlay visibility {err}");
                                 }
                             }
@@ -120,11 +205,26 @@ pub async fn entry_point(
                         OverlayPeerMessage::GameJoin(join_data) => {
                             if let Ok(res) = overlay_peer::encode_game_join(join_data).await {
                                 log::debug!("Sending game join invite to game");
-                                if let Err(err) = context_clone.socket_mut().await.write_all(&res).await {
+                                if let Err(err) = context_clone.socket_write(&res).await {
                                     error!("Failed to send game join event to the game {err}");
                                 }
                             }
                         },
+                        #[cfg(target_os = "linux")]
+                        OverlayPeerMessage::Achievement(achievement) => {
+                            if context_clone.overlay_attached().await {
+                                continue
+                            }
+                            let client_id = context_clone.client_id().await.unwrap_or_default();
+                            let settings = context_clone.settings().await;
+                            let reqwest_client = reqwest_clone.clone();
+                            tokio::spawn(async move {
+                                let config = &settings.overlay.notifications.achievements;
+                                if let Err(err) = crate::notifications::achievement_notification(&reqwest_client, &client_id, &achievement, config).await {
+                                    warn!("Failed to show achievement desktop notification {err}");
+                                }
+                            });
+                        },
                         _ => ()
                     }
                 }
@@ -138,8 +238,15 @@ pub async fn entry_point(
                             context_clone.set_offline().await
                         },
                         Ok(PusherEvent::Topic(message, topic)) => {
+                            if topic == "presence"
+                                && context_clone.client_identified().await
+                                && let Some((friend_id, online)) = crate::friends::parse_presence_message(&message)
+                                && let Ok(Some(data)) = peer_to_server::encode_persona_state_change(&user_clone, &friend_id, online).await
+                                && let Err(err) = context_clone.socket_write(&data).await {
+                                    error!("Failed to send persona state change to the game {err}");
+                                }
                             if context_clone.is_subscribed(&topic).await {
-                                if let Err(err) = context_clone.socket_mut().await.write_all(message.as_slice()).await {
+                                if let Err(err) = context_clone.socket_write(message.as_slice()).await {
                                     error!("Failed to forward topic message to socket {}", err);
                                 }
                                 debug!("Forwarded topic message");
@@ -154,16 +261,14 @@ pub async fn entry_point(
         }
     });
 
-    let shutdown_token_clone = shutdown_token.clone();
+    let shutdown_token_clone = session_token.clone();
     let context_clone = context.clone();
     let reqwest_clone = reqwest_client.clone();
     let user_clone = user_info.clone();
     let overlay_thread = tokio::spawn(async move {
-        let mut overlay_receiver = overlay_event_receiver;
-
         let pipe_name = loop {
             tokio::select! {
-                Ok((_pid, msg)) = overlay_receiver.recv() => {
+                Some(msg) = overlay_receiver.recv() => {
                     if let OverlayPeerMessage::InitConnection(socket) = msg {
                         break socket;
                     }
@@ -204,7 +309,13 @@ pub async fn entry_point(
             }
         };
 
+        context_clone.set_overlay_attached(true).await;
         let game_pid = context_clone.get_pid().await;
+        let recorder = capture::recorder_for(
+            options.record_dir.as_deref(),
+            &format!("overlay-{game_pid}"),
+        )
+        .await;
         loop {
             tokio::select! {
                 size_read = current_socket.read_u16() => {
@@ -212,8 +323,12 @@ pub async fn entry_point(
                         Ok(h_size) => {
                             let payload = utils::parse_payload(h_size, &mut current_socket).await;
                             let Ok(payload) = payload else { continue };
+                            if let Some(recorder) = &recorder && let Ok(frame) = payload.frame() {
+                                recorder.record(Direction::In, &frame).await;
+                            }
                             match handle_message(&context_clone, user_clone.clone(), &reqwest_clone, payload).await {
                                 Ok(res) => {
+                                    capture::record(recorder.as_ref(), Direction::Out, &res).await;
                                     let _ = current_socket.write_all(&res).await;
                                 },
                                 Err(err) => { if !matches!(err.kind, MessageHandlingErrorKind::Ignored) {  error!("Failed to respond to overlay {err:?}") } },
@@ -228,11 +343,10 @@ pub async fn entry_point(
                         }
                     }
                 }
-                Ok((pid,msg)) = overlay_receiver.recv() => {
-                    if pid != game_pid { continue }
+                Some(msg) = overlay_receiver.recv() => {
                     let data: Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> = match msg {
                         OverlayPeerMessage::Achievement(achievement) =>
-                            if crate::CONFIG.overlay.notifications.achievements.enabled {
+                            if context_clone.settings().await.overlay.notifications.achievements.enabled {
                                 overlay_service::achievement_notification(achievement).await
                             } else {
                                 Err(MessageHandlingError::ignored().into())
@@ -242,10 +356,12 @@ pub async fn entry_point(
                         OverlayPeerMessage::DisablePopups(data) => overlay_peer::encode_overlay_initialized(data).await,
                         _ => Err(MessageHandlingError::ignored().into())
                     };
-                    if let Ok(data) = data
-                        && let Err(err) = current_socket.write_all(&data).await {
+                    if let Ok(data) = data {
+                        capture::record(recorder.as_ref(), Direction::Out, &data).await;
+                        if let Err(err) = current_socket.write_all(&data).await {
                             error!("Failed to send web page request {err}");
                         }
+                    }
                 }
                 topic_message = topic_receiver.recv() => {
                     match topic_message {
@@ -257,6 +373,7 @@ pub async fn entry_point(
                         },
                         Ok(PusherEvent::Topic(message, topic)) => {
                             if context_clone.is_subscribed(&topic).await {
+                                capture::record(recorder.as_ref(), Direction::Out, &message).await;
                                 if let Err(err) = current_socket.write_all(message.as_slice()).await {
                                     error!("Failed to forward topic message to socket {}", err);
                                 }
@@ -271,6 +388,7 @@ pub async fn entry_point(
                 }
             }
         }
+        context_clone.set_overlay_attached(false).await;
         #[cfg(unix)]
         if let Ok(addr) = overlay_listener.local_addr()
             && let Some(path) = addr.as_pathname()
@@ -280,8 +398,34 @@ pub async fn entry_point(
     });
 
     let _ = main_socket.await;
+    context.unregister_overlay().await;
+    session_token.cancel();
     let _ = overlay_thread.await;
     sync_routine(&context, &reqwest_client, user_info.clone()).await;
+    cloud_auto_sync(&context, &reqwest_client, &user_info).await;
+    if context.client_identified().await {
+        publish_presence(&context, &reqwest_client, &user_info, false).await;
+    }
+    if let Some(client_id) = context.client_id().await
+        && let Ok(user_id) = user_info.galaxy_user_id.parse()
+    {
+        crate::lobby::leave_all(&client_id, user_id).await;
+    }
+    if let Some(client_id) = context.client_id().await {
+        hooks::trigger(HookEvent::SessionEnd {
+            client_id,
+            pid: context.get_pid().await,
+        });
+    }
+}
+
+async fn read_upstream_u16(
+    upstream: &mut Option<proxy::Upstream>,
+) -> Result<u16, tokio::io::Error> {
+    match upstream {
+        Some(upstream) => upstream.read_u16().await,
+        None => std::future::pending().await,
+    }
 }
 
 pub async fn handle_message(
@@ -291,14 +435,19 @@ pub async fn handle_message(
     payload: ProtoPayload,
 ) -> Result<Vec<u8>, MessageHandlingError> {
     let sort = payload.header.sort();
-    let type_ = payload.header.type_();
 
-    debug!("Parsing message {} {}", sort, type_);
-    debug!("payload.payload = {:?}", payload.payload);
+    if log::log_enabled!(log::Level::Debug) {
+        debug!(
+            "Parsing message {}",
+            crate::decode::decode(&payload, crate::decode::OutputFormat::Text)
+        );
+    }
     let mut result = match sort {
         1 => communication_service::entry_point(&payload, context, user_info, reqwest_client).await,
         2 => webbroker::entry_point(&payload, context).await,
-        3 => overlay_service::entry_point(&payload, context).await,
+        3 => overlay_service::entry_point(&payload, context, reqwest_client).await,
+        4 => peer_to_server::entry_point(&payload, context, user_info, reqwest_client).await,
+        5 => peer_to_peer::entry_point(&payload, context, user_info).await,
         6 => overlay_peer::entry_point(&payload, context).await,
         7 => overlay_client::entry_point(&payload, context, user_info, reqwest_client).await,
         _ => {
@@ -307,11 +456,12 @@ pub async fn handle_message(
         }
     }?;
     result.header.set_sort(sort);
-    debug!(
-        "Responding with {} {}",
-        result.header.sort(),
-        result.header.type_()
-    );
+    if log::log_enabled!(log::Level::Debug) {
+        debug!(
+            "Responding with {}",
+            crate::decode::decode(&result, crate::decode::OutputFormat::Text)
+        );
+    }
     // Prepare response
     if payload.header.has_oseq() {
         result
@@ -348,7 +498,13 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
     let current_token = token_store.get(client_id);
     if let Some(token) = current_token {
         let current_time = chrono::Utc::now();
-        if (current_time.timestamp() - token.obtain_time.timestamp()) >= 3500 {
+        let refresh_age: i64 = crate::CONFIG
+            .get()
+            .runtime
+            .token_refresh_age
+            .try_into()
+            .unwrap_or(i64::MAX);
+        if (current_time.timestamp() - token.obtain_time.timestamp()) >= refresh_age {
             debug!("Refreshing credentials for {}", client_id);
             let result = gog::users::get_token_for(
                 client_id,
@@ -361,6 +517,7 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
             match result {
                 Ok(new_token) => {
                     token_store.insert(client_id.clone(), new_token);
+                    context.set_sync_ok("token").await;
                 }
                 Err(err) => {
                     drop(token_store);
@@ -368,6 +525,7 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                         context.set_offline().await;
                     }
                     warn!("Failed to refresh the token for {} {:?}", client_id, err);
+                    report_sync_failure(context, "token", format!("{err:?}")).await;
                     return;
                 }
             }
@@ -395,6 +553,7 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                     .expect("Failed to get database connection");
                 let mut transaction = connection.begin().await.unwrap();
 
+                let mut failed = false;
                 for achievement in achievements {
                     debug!("Setting achievement {}", achievement.achievement_key());
                     let result = gog::achievements::set_achievement(
@@ -420,10 +579,15 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                                 "Failed to set achievement {} {err:?}",
                                 achievement.achievement_key()
                             );
+                            failed = true;
+                            report_sync_failure(context, "achievements", format!("{err:?}")).await;
                         }
                     }
                 }
                 transaction.commit().await.expect("Failed to save changes");
+                if !failed {
+                    context.set_sync_ok("achievements").await;
+                }
                 context.set_updated_achievements(false).await;
                 info!("Uploaded achievements");
             }
@@ -447,6 +611,7 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                     .await
                     .expect("Failed to start transaction");
 
+                let mut failed = false;
                 for stat in stats {
                     debug!("Setting stat {}", stat.stat_id());
                     let result = gog::stats::update_stat(
@@ -469,10 +634,15 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                         }
                         Err(err) => {
                             log::error!("Failed to upload statistic {} {err:?}", stat.stat_key());
+                            failed = true;
+                            report_sync_failure(context, "stats", format!("{err:?}")).await;
                         }
                     }
                 }
                 transaction.commit().await.expect("Failed to save changes");
+                if !failed {
+                    context.set_sync_ok("stats").await;
+                }
                 context.set_updated_stats(false).await;
                 info!("Uploaded stats");
             }
@@ -480,7 +650,7 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
         }
     }
 
-    if updated_leaderboards {
+    if updated_leaderboards && context.settings().await.leaderboard_uploads {
         info!("Syncing leaderboards");
 
         let changed_leaderboards = db::gameplay::get_leaderboards_score_changed(context).await;
@@ -498,6 +668,7 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                     .await
                     .expect("Failed to start transaction");
 
+                let mut failed = false;
                 for (id, score, _rank, _entry_total_count, force, details) in entries {
                     let details = if details.is_empty() {
                         None
@@ -527,6 +698,8 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                         }
                         Err(err) => {
                             warn!("More details {}", err);
+                            failed = true;
+                            report_sync_failure(context, "leaderboards", format!("{err:?}")).await;
                             if let MessageHandlingErrorKind::Network(networ_error) = err.kind
                                 && networ_error.status().is_some_and(|s| s == 409)
                             {
@@ -565,6 +738,9 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
                 }
 
                 transaction.commit().await.expect("Failed to save changes");
+                if !failed {
+                    context.set_sync_ok("leaderboards").await;
+                }
                 info!("Leaderboards synced");
                 context.set_updated_leaderboards(false).await;
             }
@@ -572,3 +748,161 @@ async fn sync_routine(context: &HandlerContext, reqwest_client: &Client, user_in
         }
     }
 }
+
+// Product id lookup and presence of a new session run in the background after auth response
+async fn spawn_session_sync(
+    context: &Arc<HandlerContext>,
+    reqwest_client: &Client,
+    user_info: &Arc<UserInfo>,
+) {
+    let product_scan = context.take_pending_product_scan().await;
+    let session_sync = context.take_pending_session_sync().await;
+    if !product_scan && !session_sync {
+        return;
+    }
+    let context = context.clone();
+    let reqwest_client = reqwest_client.clone();
+    let user_info = user_info.clone();
+    tokio::spawn(async move {
+        // Presence includes the product id, so it's looked up first
+        if product_scan
+            && let Some(client_id) = context.client_id().await
+            && let Some(product_id) = game_info::find_running_product_id(&client_id).await
+        {
+            context.set_product_id(Some(product_id)).await;
+        }
+        if session_sync {
+            publish_presence(&context, &reqwest_client, &user_info, true).await;
+        }
+    });
+}
+
+/// Sync cloud saves before the game gets the auth response. Sync is given up after
+/// `runtime.cloud_sync_timeout`, so slow network doesn't hold the game launch forever
+pub async fn cloud_start_sync(
+    context: &HandlerContext,
+    reqwest_client: &Client,
+    user_info: &UserInfo,
+) {
+    let timeout = time::Duration::from_secs(crate::CONFIG.get().runtime.cloud_sync_timeout);
+    let sync = cloud_auto_sync(context, reqwest_client, user_info);
+    if time::timeout(timeout, sync).await.is_err() {
+        error!(
+            "Cloud sync didn't finish in {} seconds, starting the game without it",
+            timeout.as_secs()
+        );
+        report_sync_failure(context, "cloud", String::from("Cloud sync timed out")).await;
+    }
+}
+
+/// Sync cloud saves when it's enabled for the game, used at session start and end
+pub async fn cloud_auto_sync(
+    context: &HandlerContext,
+    reqwest_client: &Client,
+    user_info: &UserInfo,
+) {
+    let settings = context.settings().await;
+    let Some(cloud) = settings.cloud.as_ref().filter(|cloud| cloud.auto_sync) else {
+        return;
+    };
+    if !context.is_online().await {
+        return;
+    }
+    let Some(client_id) = context.client_id().await else {
+        return;
+    };
+    let result = crate::cloud::sync(
+        context.token_store(),
+        &client_id,
+        &user_info.galaxy_user_id,
+        reqwest_client,
+        &cloud.location,
+        &cloud.path,
+        cloud.conflict,
+    )
+    .await;
+    match result {
+        Ok(summary) if !summary.conflicts.is_empty() => {
+            report_sync_failure(
+                context,
+                "cloud",
+                format!("Conflicts in {}", summary.conflicts.join(", ")),
+            )
+            .await
+        }
+        Ok(_) => context.set_sync_ok("cloud").await,
+        Err(err) => {
+            error!("Failed to sync cloud saves {err}");
+            report_sync_failure(context, "cloud", err.to_string()).await;
+        }
+    }
+}
+
+lazy_static! {
+    // Newest presence not published yet, publishing tasks skip the ones replaced in the meantime
+    static ref PENDING_PRESENCE: std::sync::Mutex<Option<Presence>> = std::sync::Mutex::new(None);
+    static ref PRESENCE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// Let friends know what the user is doing, in game shows the product and rich presence.
+/// Presence is published in the background, so games aren't waiting on presence service
+pub async fn publish_presence(
+    context: &HandlerContext,
+    reqwest_client: &Client,
+    user_info: &UserInfo,
+    in_game: bool,
+) {
+    if !context.is_online().await {
+        return;
+    }
+    let presence = match context.product_id().await {
+        Some(product_id) if in_game => {
+            let presence = Presence::InGame {
+                product_id,
+                rich_presence: context.rich_presence().await,
+            };
+            context.remember_presence(presence.clone());
+            presence
+        }
+        // Another game may still be running, show it instead of going back to online
+        _ => context
+            .overlay_registry()
+            .latest_presence()
+            .unwrap_or(Presence::Online),
+    };
+    *PENDING_PRESENCE.lock().unwrap() = Some(presence);
+    let token_store = context.token_store().clone();
+    let user_id = user_info.galaxy_user_id.clone();
+    let reqwest_client = reqwest_client.clone();
+    tokio::spawn(async move {
+        // Tasks publish one at a time, so an older presence can't overwrite a newer one
+        let _guard = PRESENCE_LOCK.lock().await;
+        let Some(presence) = PENDING_PRESENCE.lock().unwrap().take() else {
+            return;
+        };
+        if let Err(err) =
+            gog::presence::set_presence(&token_store, &user_id, &presence, &reqwest_client).await
+        {
+            warn!("Failed to publish presence {:?}", err);
+        }
+    });
+}
+
+/// Drop presence not published yet and wait for the one being published
+pub async fn discard_pending_presence() {
+    let _guard = PRESENCE_LOCK.lock().await;
+    PENDING_PRESENCE.lock().unwrap().take();
+}
+
+// Hook runs when sync of kind starts failing, not on every retry
+async fn report_sync_failure(context: &HandlerContext, kind: &'static str, error: String) {
+    if !context.set_sync_failing(kind).await {
+        debug!("{kind} sync is still failing");
+        return;
+    }
+    hooks::trigger(HookEvent::SyncFailed {
+        client_id: context.client_id().await.unwrap_or_default(),
+        kind,
+        error,
+    });
+}
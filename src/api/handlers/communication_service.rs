@@ -3,6 +3,8 @@ use crate::api::gog::stats::FieldValue;
 use crate::api::handlers::context::HandlerContext;
 use crate::api::structs::{DataSource, IDType, UserInfo};
 use crate::db::gameplay::{set_stat_float, set_stat_int};
+use crate::game_info;
+use crate::hooks::{self, HookEvent};
 use crate::paths::REDISTS_STORAGE;
 use crate::{constants, db};
 use base64::prelude::*;
@@ -11,7 +13,6 @@ use log::{debug, info, warn};
 use protobuf::{Enum, Message};
 use reqwest::{Client, StatusCode};
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
 
 use crate::proto::common_utils::ProtoPayload;
 
@@ -68,6 +69,8 @@ pub async fn entry_point(
         set_leaderboard_score(payload, context, user_info, reqwest_client).await
     } else if message_type == MessageType::CREATE_LEADERBOARD_REQUEST.value() {
         create_leaderboard(payload, context, user_info, reqwest_client).await
+    } else if message_type == MessageType::IS_DLC_OWNED_REQUEST.value() {
+        is_dlc_owned(payload, context, user_info, reqwest_client).await
     } else if message_type == MessageType::START_GAME_SESSION_REQUEST.value() {
         start_game_session(payload, context, user_info, reqwest_client).await
     } else {
@@ -137,6 +140,16 @@ async fn auth_info_request(
     if !context.client_identified().await {
         context.identify_client(client_id, client_secret, pid).await;
         info!("Client identified as {} {}", client_id, client_secret);
+        let settings = context.settings().await;
+        let product_id = game_info::resolve_product_id(client_id, pid, &settings).await;
+        if product_id.is_none() {
+            context.set_pending_product_scan().await;
+        }
+        context.set_product_id(product_id).await;
+        hooks::trigger(HookEvent::SessionStart {
+            client_id: client_id.to_owned(),
+            pid,
+        });
     }
 
     info!("Game PID: {}", pid);
@@ -151,14 +164,21 @@ async fn auth_info_request(
     };
 
     // Obtain the token (at least attempt to)
-    let new_token = gog::users::get_token_for(
-        client_id,
-        client_secret,
-        refresh_token.as_str(),
-        reqwest_client,
-        openid,
-    )
-    .await;
+    let new_token = if context.settings().await.offline {
+        info!("Offline mode is enabled for {}", client_id);
+        None
+    } else {
+        Some(
+            gog::users::get_token_for(
+                client_id,
+                client_secret,
+                refresh_token.as_str(),
+                reqwest_client,
+                openid,
+            )
+            .await,
+        )
+    };
 
     if let Err(err) = context
         .setup_database(client_id, &user_info.galaxy_user_id)
@@ -196,7 +216,7 @@ async fn auth_info_request(
         buffer.extend(header_size.to_be_bytes());
         buffer.extend(header_buf);
         buffer.extend(data_buf);
-        let _ = context.socket_mut().await.write_all(&buffer).await;
+        let _ = context.socket_write(&buffer).await;
     }
 
     // Use new refresh_token to prepare response
@@ -204,19 +224,25 @@ async fn auth_info_request(
     header.set_type(MessageType::AUTH_INFO_RESPONSE.value().try_into().unwrap());
 
     let mut content = AuthInfoResponse::new();
+    let mut authenticated = false;
     match new_token {
-        Ok(token) => {
+        Some(Ok(token)) => {
             let mut token_storage = context.token_store().lock().await;
             token_storage.insert(String::from(client_id), token.clone());
             content.set_refresh_token(token.refresh_token);
             context.set_online().await;
+            // Presence doesn't have to hold the game launch, it's published after the response
+            context.set_pending_session_sync().await;
+            authenticated = true;
         }
-        Err(err) => {
-            warn!("There was an error getting the access token {:?}", err);
-            if let Some(status) = err.status() {
-                // user doesn't own the game
-                if StatusCode::FORBIDDEN == status {
-                    return Err(MessageHandlingError::unauthorized());
+        result => {
+            if let Some(Err(err)) = result {
+                warn!("There was an error getting the access token {:?}", err);
+                if let Some(status) = err.status() {
+                    // user doesn't own the game
+                    if StatusCode::FORBIDDEN == status {
+                        return Err(MessageHandlingError::unauthorized());
+                    }
                 }
             }
             // Check if we can continue offline
@@ -228,6 +254,11 @@ async fn auth_info_request(
             }
         }
     };
+    // Game reads its saves once it's authenticated, they have to be in place before the response
+    if authenticated {
+        super::cloud_start_sync(context, reqwest_client, &user_info).await;
+    }
+
     content.set_region(REGION_WORLD_WIDE); // TODO: Handle China region
     content.set_environment_type(ENVIRONMENT_PRODUCTION);
     let user_id = IDType::User(user_info.galaxy_user_id.parse().unwrap());
@@ -480,21 +511,34 @@ async fn get_user_achievements(
     reqwest_client: &Client,
 ) -> Result<ProtoPayload, MessageHandlingError> {
     let client_id = context.client_id().await.unwrap();
+    let language = context.settings().await.language.clone();
     let online_achievements = gog::achievements::fetch_achievements(
         context.token_store(),
         &client_id,
         &user_info.galaxy_user_id,
+        &language,
         reqwest_client,
     )
     .await;
     let local_achievements = db::gameplay::get_achievements(context, false).await;
 
+    let mut served_language = language.clone();
     let mut achievements_source = DataSource::Online;
     let (achievements, achievements_mode) = match online_achievements {
         Ok(achievements) => achievements,
         Err(_) => match local_achievements {
             Ok(achievements) => {
                 achievements_source = DataSource::Local;
+                if let Some(stored_language) =
+                    db::gameplay::get_achievements_language(&context.db_connection().await).await
+                    && stored_language != language
+                {
+                    warn!(
+                        "Serving achievements in {} instead of {}, they will be updated once online",
+                        stored_language, language
+                    );
+                    served_language = stored_language;
+                }
                 achievements
             }
             Err(_) => panic!("Unable to load achievements"),
@@ -506,6 +550,7 @@ async fn get_user_achievements(
             context.db_connection().await,
             &achievements,
             &achievements_mode,
+            &language,
         )
         .await
         {
@@ -523,7 +568,7 @@ async fn get_user_achievements(
     );
     let mut content = GetUserAchievementsResponse::new();
     content.set_achievements_mode(achievements_mode);
-    content.set_language(crate::LOCALE.clone());
+    content.set_language(served_language);
 
     for achievement in achievements {
         let mut proto_achievement = UserAchievement::new();
@@ -794,19 +839,37 @@ async fn set_leaderboard_score(
     } else {
         Some(details.clone())
     };
-    let response = gog::leaderboards::post_leaderboard_score(
-        context,
-        reqwest_client,
-        &user_info.galaxy_user_id,
-        request.leaderboard_id().try_into().unwrap(),
-        request.score(),
-        request.force_update(),
-        post_details,
-    )
-    .await;
+    let response = if context.settings().await.leaderboard_uploads {
+        Some(
+            gog::leaderboards::post_leaderboard_score(
+                context,
+                reqwest_client,
+                &user_info.galaxy_user_id,
+                request.leaderboard_id().try_into().unwrap(),
+                request.score(),
+                request.force_update(),
+                post_details,
+            )
+            .await,
+        )
+    } else {
+        info!("Leaderboard uploads are disabled, saving score locally");
+        None
+    };
 
     match response {
-        Ok(data) => {
+        None => {
+            db::gameplay::set_leaderboard_score(
+                context,
+                &id,
+                request.score(),
+                request.force_update(),
+                &details,
+            )
+            .await
+            .map_err(MessageHandlingError::db)?;
+        }
+        Some(Ok(data)) => {
             db::gameplay::set_leaderboard_score(
                 context,
                 &id,
@@ -825,6 +888,26 @@ async fn set_leaderboard_score(
             .await
             .map_err(MessageHandlingError::db)?;
 
+            let client_id = context.client_id().await.unwrap_or_default();
+            if data.old_rank != data.new_rank {
+                hooks::trigger(HookEvent::LeaderboardRankChanged {
+                    client_id: client_id.clone(),
+                    leaderboard_id: id.clone(),
+                    score: request.score(),
+                    old_rank: data.old_rank,
+                    new_rank: data.new_rank,
+                    entry_total_count: data.leaderboard_entry_total_count,
+                });
+            }
+            hooks::trigger(HookEvent::LeaderboardScorePosted {
+                client_id,
+                leaderboard_id: id.clone(),
+                score: request.score(),
+                old_rank: data.old_rank,
+                new_rank: data.new_rank,
+                entry_total_count: data.leaderboard_entry_total_count,
+            });
+
             let mut proto_data = SetLeaderboardScoreResponse::new();
             proto_data.set_score(request.score());
             proto_data.set_old_rank(data.old_rank);
@@ -836,7 +919,7 @@ async fn set_leaderboard_score(
             header.set_size(payload.len().try_into().unwrap());
             return Ok(ProtoPayload { header, payload });
         }
-        Err(err) => {
+        Some(Err(err)) => {
             log::error!("Failed to set leaderboard score, {:?}", err);
 
             db::gameplay::set_leaderboard_score(
@@ -938,6 +1021,67 @@ async fn create_leaderboard(
     Ok(ProtoPayload { header, payload })
 }
 
+// Owned products are fetched once per session and cached for offline use
+async fn is_product_owned(
+    context: &HandlerContext,
+    reqwest_client: &Client,
+    product_id: u64,
+) -> Result<bool, MessageHandlingError> {
+    let database = context.db_connection().await;
+    if context.is_online().await && !context.owned_products_fetched().await {
+        match gog::library::fetch_owned_products(context.token_store(), reqwest_client).await {
+            Ok(products) => {
+                info!("Fetched {} owned products", products.len());
+                db::gameplay::set_owned_products(database.clone(), &products)
+                    .await
+                    .map_err(MessageHandlingError::db)?;
+                context.set_owned_products_fetched(true).await;
+            }
+            Err(err) => warn!("Failed to fetch owned products {:?}", err),
+        }
+    }
+
+    if !db::gameplay::has_owned_products(&database).await {
+        warn!("No owned products cached locally, assuming {product_id} is not owned");
+        return Ok(false);
+    }
+    db::gameplay::is_product_owned(&database, product_id)
+        .await
+        .map_err(MessageHandlingError::db)
+}
+
+async fn is_dlc_owned(
+    proto_payload: &ProtoPayload,
+    context: &HandlerContext,
+    _user_info: Arc<UserInfo>,
+    reqwest_client: &Client,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = IsDlcOwnedRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    let product_id = request.product_id();
+
+    let owned = is_product_owned(context, reqwest_client, product_id).await?;
+    info!("DLC {} owned: {}", product_id, owned);
+
+    let mut header = Header::new();
+    header.set_type(
+        MessageType::IS_DLC_OWNED_RESPONSE
+            .value()
+            .try_into()
+            .unwrap(),
+    );
+
+    let mut response = IsDlcOwnedResponse::new();
+    response.set_is_owned(owned);
+
+    let payload = response
+        .write_to_bytes()
+        .map_err(MessageHandlingError::proto)?;
+    header.set_size(payload.len().try_into().unwrap());
+
+    Ok(ProtoPayload { header, payload })
+}
+
 async fn start_game_session(
     proto_payload: &ProtoPayload,
     context: &HandlerContext,
@@ -1,14 +1,21 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
-use crate::api::gog::overlay::OverlayPeerMessage;
+use crate::api::gog::presence::Presence;
+use crate::capture::{Direction, Recorder};
+use crate::config::GameSettings;
 use crate::constants::TokenStorage;
+use crate::lobby::LobbyEvent;
+use crate::overlay::{OverlayRegistry, SessionChannels};
+use crate::proto::common_utils::ProtoPayload;
 use crate::workarounds::StatAchievementWorkaround;
 use crate::{db, paths};
 use derive_getters::Getters;
 use sqlx::SqlitePool;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
-use tokio::sync::{Mutex, MutexGuard, broadcast};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Mutex, MutexGuard};
 
 pub struct State {
     is_online: bool,
@@ -19,27 +26,46 @@ pub struct State {
     updated_achievements: bool,
     updated_stats: bool,
     updated_leaderboards: bool,
+    owned_products_fetched: bool,
+    rich_presence: HashMap<String, String>,
+    product_id: Option<String>,
     pid: u32,
+    overlay_attached: bool,
+    /// Cloud saves and presence wait for the auth response to be sent
+    pending_session_sync: bool,
+    pending_product_scan: bool,
+    /// Kinds of sync currently failing, so hooks run once per failure
+    failing_syncs: HashSet<&'static str>,
+    settings: Arc<GameSettings>,
 }
 
 #[derive(Getters)]
 pub struct HandlerContext {
     socket: Mutex<TcpStream>,
     token_store: TokenStorage,
-    overlay_sender: broadcast::Sender<(u32, OverlayPeerMessage)>,
+    overlay_registry: OverlayRegistry,
+    #[getter(skip)]
+    overlay_channels: SessionChannels,
+    #[getter(skip)]
+    lobby_events: UnboundedSender<LobbyEvent>,
     #[getter(skip)]
     db_connection: Mutex<Option<SqlitePool>>,
     #[getter(skip)]
     state: Mutex<State>,
     #[getter(skip)]
     progress_workarounds: Mutex<Vec<StatAchievementWorkaround>>,
+    #[getter(skip)]
+    recorder: Option<Recorder>,
 }
 
 impl HandlerContext {
     pub fn new(
         socket: TcpStream,
         token_store: TokenStorage,
-        achievement_sender: broadcast::Sender<(u32, OverlayPeerMessage)>,
+        overlay_registry: OverlayRegistry,
+        overlay_channels: SessionChannels,
+        lobby_events: UnboundedSender<LobbyEvent>,
+        recorder: Option<Recorder>,
     ) -> Self {
         let state = Mutex::new(State {
             is_online: false,
@@ -50,14 +76,25 @@ impl HandlerContext {
             updated_achievements: false,
             updated_stats: false,
             updated_leaderboards: true,
+            owned_products_fetched: false,
+            rich_presence: HashMap::new(),
+            product_id: None,
             pid: 0,
+            overlay_attached: false,
+            pending_session_sync: false,
+            pending_product_scan: false,
+            failing_syncs: HashSet::new(),
+            settings: Arc::new(crate::CONFIG.get().resolve(None)),
         });
         Self {
             socket: Mutex::new(socket),
             token_store,
-            overlay_sender: achievement_sender,
+            overlay_registry,
+            overlay_channels,
+            lobby_events,
             db_connection: Mutex::new(None),
             progress_workarounds: Mutex::new(Vec::new()),
+            recorder,
             state,
         }
     }
@@ -70,16 +107,74 @@ impl HandlerContext {
         self.socket.lock().await.read_u16().await
     }
 
+    pub async fn socket_write(&self, data: &[u8]) -> Result<(), std::io::Error> {
+        crate::capture::record(self.recorder.as_ref(), Direction::Out, data).await;
+        self.socket.lock().await.write_all(data).await
+    }
+
+    pub async fn record_incoming(&self, payload: &ProtoPayload) {
+        if let Some(recorder) = &self.recorder
+            && let Ok(frame) = payload.frame()
+        {
+            recorder.record(Direction::In, &frame).await;
+        }
+    }
+
     pub async fn identify_client(&self, client_id: &str, client_secret: &str, pid: u32) {
         let mut state = self.state.lock().await;
         state.client_identified = true;
         state.client_id = Some(client_id.to_string());
         state.client_secret = Some(client_secret.to_string());
         state.pid = pid;
+        state.settings = Arc::new(crate::CONFIG.get().resolve(Some(client_id)));
+        self.overlay_registry
+            .register(pid, Some(client_id), &self.overlay_channels);
+        crate::lobby::subscribe(client_id, &self.lobby_events);
+    }
+
+    /// Resolve game settings again, used after configuration reload
+    pub async fn refresh_settings(&self) {
+        let mut state = self.state.lock().await;
+        state.settings = Arc::new(crate::CONFIG.get().resolve(state.client_id.as_deref()));
+        if state.settings.offline {
+            state.is_online = false;
+        }
+    }
+
+    pub async fn settings(&self) -> Arc<GameSettings> {
+        self.state.lock().await.settings.clone()
     }
 
     pub async fn set_online(&self) {
-        self.state.lock().await.is_online = true
+        let mut state = self.state.lock().await;
+        if !state.settings.offline {
+            state.is_online = true
+        }
+    }
+
+    pub async fn set_rich_presence(&self, key: String, value: String) {
+        self.state.lock().await.rich_presence.insert(key, value);
+    }
+
+    pub async fn delete_rich_presence(&self, key: &str) {
+        self.state.lock().await.rich_presence.remove(key);
+    }
+
+    pub async fn clear_rich_presence(&self) {
+        self.state.lock().await.rich_presence.clear()
+    }
+
+    /// GOG product id of the connected game, when it could be detected
+    pub async fn product_id(&self) -> Option<String> {
+        self.state.lock().await.product_id.clone()
+    }
+
+    pub async fn set_product_id(&self, product_id: Option<String>) {
+        self.state.lock().await.product_id = product_id;
+    }
+
+    pub async fn rich_presence(&self) -> HashMap<String, String> {
+        self.state.lock().await.rich_presence.clone()
     }
 
     pub async fn subscribe_topic(&self, topic: String) {
@@ -103,13 +198,52 @@ impl HandlerContext {
     pub async fn set_updated_leaderboards(&self, value: bool) {
         self.state.lock().await.updated_leaderboards = value
     }
+    pub async fn set_owned_products_fetched(&self, value: bool) {
+        self.state.lock().await.owned_products_fetched = value
+    }
+    pub async fn set_overlay_attached(&self, value: bool) {
+        self.state.lock().await.overlay_attached = value
+    }
+
+    pub async fn set_pending_session_sync(&self) {
+        self.state.lock().await.pending_session_sync = true
+    }
+    pub async fn take_pending_session_sync(&self) -> bool {
+        std::mem::take(&mut self.state.lock().await.pending_session_sync)
+    }
+
+    pub async fn set_pending_product_scan(&self) {
+        self.state.lock().await.pending_product_scan = true
+    }
+    pub async fn take_pending_product_scan(&self) -> bool {
+        std::mem::take(&mut self.state.lock().await.pending_product_scan)
+    }
+
+    /// Mark sync of kind as failing, returns false when it was already failing
+    pub async fn set_sync_failing(&self, kind: &'static str) -> bool {
+        self.state.lock().await.failing_syncs.insert(kind)
+    }
+    pub async fn set_sync_ok(&self, kind: &'static str) {
+        self.state.lock().await.failing_syncs.remove(kind);
+    }
 
     pub async fn load_workarounds(&self, client_id: &str) -> Result<(), tokio::io::Error> {
-        let stats_path = paths::WORKAROUNDS.join(format!("{client_id}.progress"));
+        let stats_path = match &self.settings().await.workarounds {
+            Some(path) => paths::WORKAROUNDS.join(path),
+            None => paths::WORKAROUNDS.join(format!("{client_id}.progress")),
+        };
         let file = tokio::fs::OpenOptions::new()
             .read(true)
             .open(stats_path)
-            .await?;
+            .await;
+        let file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                // Workarounds may have been removed since the last load
+                self.progress_workarounds.lock().await.clear();
+                return Err(err);
+            }
+        };
         let reader = BufReader::new(file);
         let mut lines = reader.lines();
         let mut stat_list: Vec<StatAchievementWorkaround> = Vec::new();
@@ -146,12 +280,6 @@ impl HandlerContext {
             .execute(&mut *connection)
             .await?;
 
-        // This may already exist, we don't care
-        let _ = sqlx::query("INSERT INTO database_info VALUES ('language', $1) ON CONFLICT(key) DO UPDATE SET value = excluded.value")
-            .bind(crate::LOCALE.as_str())
-            .execute(&mut *connection)
-            .await;
-
         Ok(())
     }
 
@@ -185,6 +313,13 @@ impl HandlerContext {
     pub async fn updated_leaderboards(&self) -> bool {
         self.state.lock().await.updated_leaderboards
     }
+    pub async fn owned_products_fetched(&self) -> bool {
+        self.state.lock().await.owned_products_fetched
+    }
+
+    pub async fn overlay_attached(&self) -> bool {
+        self.state.lock().await.overlay_attached
+    }
 
     pub async fn get_pid(&self) -> u32 {
         self.state.lock().await.pid
@@ -203,10 +338,27 @@ impl HandlerContext {
     }
 
     pub async fn register_overlay_listener(&self, pid: u32, listener: String) {
-        self.state.lock().await.pid = pid;
+        let client_id = {
+            let mut state = self.state.lock().await;
+            state.pid = pid;
+            state.client_id.clone()
+        };
+
+        self.overlay_registry
+            .register(pid, client_id.as_deref(), &self.overlay_channels);
+        self.overlay_registry.register_listener(pid, listener);
+    }
+
+    /// Keep the presence of this game so it can be restored when another game closes
+    pub fn remember_presence(&self, presence: Presence) {
+        self.overlay_registry
+            .set_presence(&self.overlay_channels, presence);
+    }
 
-        self.overlay_sender
-            .send((pid, OverlayPeerMessage::InitConnection(listener)))
-            .ok();
+    /// Stop routing overlay messages to this connection
+    pub async fn unregister_overlay(&self) {
+        let pid = self.get_pid().await;
+        self.overlay_registry
+            .unregister(pid, &self.overlay_channels);
     }
 }
@@ -1,16 +1,15 @@
 use std::sync::Arc;
 
-use crate::CONFIG;
 use crate::api::structs::UserInfo;
+use crate::products;
 use crate::proto::galaxy_protocols_overlay_for_client::*;
 use crate::proto::{common_utils::ProtoPayload, gog_protocols_pb};
-use log::warn;
+use log::{debug, warn};
 use protobuf::{Enum, Message};
 use serde_json::json;
 
 use super::{MessageHandlingError, MessageHandlingErrorKind, context::HandlerContext};
 
-// THIS CODE ARE MOSTLY STUBS FOR OVERLAY
 // The Galaxy Overlay has ties with GOG Galaxy Client, and expects support for the same methods
 // that are normally handled via CEF's IPC.
 
@@ -39,11 +38,11 @@ pub async fn entry_point(
 
 async fn overlay_data_request(
     _payload: &ProtoPayload,
-    _context: &HandlerContext,
+    context: &HandlerContext,
     user_info: Arc<UserInfo>,
     reqwest_client: &reqwest::Client,
 ) -> Result<ProtoPayload, MessageHandlingError> {
-    let game_id: String = std::env::var("HEROIC_APP_NAME").unwrap_or_default();
+    let game_id = context.product_id().await.unwrap_or_default();
     let default_data = json! ({
         "id": "",
         "title": "Comet",
@@ -53,38 +52,17 @@ async fn overlay_data_request(
             "logo2x": "https://raw.githubusercontent.com/Heroic-Games-Launcher/HeroicGamesLauncher/main/public/icon.png",
         }
     });
-    let game_details = if !game_id.is_empty() {
-        if let Ok(res) = reqwest_client
-            .get(format!("https://api.gog.com/products/{}", game_id))
-            .send()
+    let game_details =
+        products::product_details(&game_id, reqwest_client, context.is_online().await)
             .await
-        {
-            if let Ok(mut res) = res.json::<serde_json::Value>().await {
-                if let Some(serde_json::Value::Object(images)) = res.get_mut("images") {
-                    for (_key, url_value) in images.iter_mut() {
-                        if let serde_json::Value::String(url) = url_value
-                            && url.starts_with("//")
-                        {
-                            *url_value = serde_json::Value::String(format!("https:{}", url));
-                        }
-                    }
-                }
-                res
-            } else {
-                default_data
-            }
-        } else {
-            default_data
-        }
-    } else {
-        default_data
-    };
+            .unwrap_or(default_data);
 
     #[cfg(not(debug_assertions))]
     let log_level = 5;
     #[cfg(debug_assertions)]
     let log_level = 8;
 
+    let config = context.settings().await;
     let init_data = json!(
     {
       "Languages": [
@@ -107,22 +85,22 @@ async fn overlay_data_request(
         { "Code": "ro", "EnglishName": "Romanian", "NativeName": "Română" }
       ],
       "SettingsData": {
-        "languageCode": crate::LOCALE.clone(),
-        "notifChatMessage": { "overlay": CONFIG.overlay.notifications.chat.enabled },
+        "languageCode": config.language.clone(),
+        "notifChatMessage": { "overlay": config.overlay.notifications.chat.enabled },
         "notifDownloadStatus": { "overlay": true },
-        "notifFriendInvite": { "overlay": CONFIG.overlay.notifications.friend_invite.enabled },
-        "notifFriendOnline": { "overlay": CONFIG.overlay.notifications.friend_online.enabled },
-        "notifFriendStartsGame": { "overlay":CONFIG.overlay.notifications.friend_game_start.enabled},
-        "notifGameInvite": { "overlay": CONFIG.overlay.notifications.game_invite.enabled},
-        "notifSoundChatMessage": { "overlay": CONFIG.overlay.notifications.chat.sound },
+        "notifFriendInvite": { "overlay": config.overlay.notifications.friend_invite.enabled },
+        "notifFriendOnline": { "overlay": config.overlay.notifications.friend_online.enabled },
+        "notifFriendStartsGame": { "overlay":config.overlay.notifications.friend_game_start.enabled},
+        "notifGameInvite": { "overlay": config.overlay.notifications.game_invite.enabled},
+        "notifSoundChatMessage": { "overlay": config.overlay.notifications.chat.sound },
         "notifSoundDownloadStatus": false,
-        "notifSoundFriendInvite": { "overlay": CONFIG.overlay.notifications.friend_invite.sound },
-        "notifSoundFriendOnline": { "overlay": CONFIG.overlay.notifications.friend_online.sound },
-        "notifSoundFriendStartsGame": { "overlay": CONFIG.overlay.notifications.friend_game_start.sound },
-        "notifSoundGameInvite": { "overlay": CONFIG.overlay.notifications.game_invite.sound },
-        "notifSoundVolume": CONFIG.overlay.notification_volume.clamp(0, 100),
+        "notifSoundFriendInvite": { "overlay": config.overlay.notifications.friend_invite.sound },
+        "notifSoundFriendOnline": { "overlay": config.overlay.notifications.friend_online.sound },
+        "notifSoundFriendStartsGame": { "overlay": config.overlay.notifications.friend_game_start.sound },
+        "notifSoundGameInvite": { "overlay": config.overlay.notifications.game_invite.sound },
+        "notifSoundVolume": config.overlay.notification_volume.clamp(0, 100),
         "showFriendsSidebar": true,
-        "overlayNotificationsPosition": CONFIG.overlay.position.to_string(),
+        "overlayNotificationsPosition": config.overlay.position.to_string(),
         "store": {}
       },
       "Config": {
@@ -184,7 +162,7 @@ async fn overlay_data_request(
 // Thanks, I hate it
 async fn client_request(
     payload: &ProtoPayload,
-    _context: &HandlerContext,
+    context: &HandlerContext,
     reqwest_client: &reqwest::Client,
 ) -> Result<ProtoPayload, MessageHandlingError> {
     let request = OverlayToClientRequest::parse_from_bytes(&payload.payload)
@@ -192,14 +170,19 @@ async fn client_request(
     let parsed_request: serde_json::Value =
         serde_json::from_str(request.data()).map_err(MessageHandlingError::json)?;
 
-    let command = parsed_request.get("Command");
-    let json_data: serde_json::Value = match command {
-        Some(serde_json::Value::String(product_details_key))
-            if product_details_key == "FetchProductDetails" =>
-        {
-            load_products(parsed_request, reqwest_client).await
+    let command = parsed_request
+        .get("Command")
+        .and_then(|command| command.as_str())
+        .unwrap_or_default();
+    let arguments = parsed_request.get("Arguments").cloned().unwrap_or_default();
+    debug!("Overlay command {command}");
+    let json_data = match command {
+        "FetchProductDetails" => load_products(&arguments, context, reqwest_client).await,
+        // Older overlay builds expect an empty object for anything comet doesn't know
+        _ => {
+            warn!("Unsupported overlay command {command}");
+            json!({})
         }
-        _ => json!({}),
     };
 
     let mut res = OverlayToClientResponse::new();
@@ -222,36 +205,21 @@ async fn client_request(
 }
 
 async fn load_products(
-    parsed_request: serde_json::Value,
+    arguments: &serde_json::Value,
+    context: &HandlerContext,
     reqwest_client: &reqwest::Client,
 ) -> serde_json::Value {
-    if let Some(arguments) = parsed_request.get("Arguments")
-        && let Some(ids) = arguments.get("ProductIds")
-    {
-        let ids = ids.as_array().unwrap();
-        let mut products: Vec<serde_json::Value> = Vec::with_capacity(ids.len());
-        for id in ids {
-            if let Ok(res) = reqwest_client
-                .get(format!(
-                    "https://api.gog.com/products/{}",
-                    id.as_str().unwrap()
-                ))
-                .send()
-                .await
-                && let Ok(mut data) = res.json::<serde_json::Value>().await
-            {
-                if let Some(serde_json::Value::Object(images)) = data.get_mut("images") {
-                    for (_key, url_value) in images.iter_mut() {
-                        if let serde_json::Value::String(url) = url_value
-                            && url.starts_with("//")
-                        {
-                            *url_value = serde_json::Value::String(format!("https:{}", url));
-                        }
-                    }
-                }
-                products.push(data);
-            }
-        }
+    if let Some(ids) = arguments.get("ProductIds").and_then(|ids| ids.as_array()) {
+        let ids: Vec<String> = ids
+            .iter()
+            .filter_map(|id| match id {
+                serde_json::Value::String(id) => Some(id.clone()),
+                serde_json::Value::Number(id) => Some(id.to_string()),
+                _ => None,
+            })
+            .collect();
+        let products =
+            products::products_details(&ids, reqwest_client, context.is_online().await).await;
         return json!({
             "Command": "ProductDetailsUpdate",
             "Arguments": {
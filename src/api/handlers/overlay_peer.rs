@@ -38,7 +38,7 @@ async fn show_web_page(
     let request = ShowWebPage::parse_from_bytes(&payload.payload)?;
     let pid = context.get_pid().await;
     let msg = OverlayPeerMessage::OpenWebPage(request.url().to_owned());
-    let _ = context.overlay_sender().send((pid, msg));
+    context.overlay_registry().send(pid, msg);
     Ok(())
 }
 
@@ -49,7 +49,7 @@ async fn visibility_change(
     let request = VisibilityChangeNotification::parse_from_bytes(&payload.payload)?;
     let pid = context.get_pid().await;
     let msg = OverlayPeerMessage::VisibilityChange(request.visible());
-    let _ = context.overlay_sender().send((pid, msg));
+    context.overlay_registry().send(pid, msg);
     Ok(())
 }
 
@@ -60,7 +60,7 @@ async fn show_invitation(
     let request = ShowInvitationDialog::parse_from_bytes(&payload.payload)?;
     let pid = context.get_pid().await;
     let msg = OverlayPeerMessage::InvitationDialog(request.connection_string().to_owned());
-    let _ = context.overlay_sender().send((pid, msg));
+    context.overlay_registry().send(pid, msg);
     Ok(())
 }
 
@@ -75,7 +75,7 @@ async fn game_join(
         request.client_id().to_owned(),
         request.connection_string().to_owned(),
     ));
-    let _ = context.overlay_sender().send((pid, msg));
+    context.overlay_registry().send(pid, msg);
     Ok(())
 }
 
@@ -85,7 +85,7 @@ async fn overlay_initialized(
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let pid = context.get_pid().await;
     let msg = OverlayPeerMessage::DisablePopups(payload.payload.clone());
-    let _ = context.overlay_sender().send((pid, msg));
+    context.overlay_registry().send(pid, msg);
     Ok(())
 }
 
@@ -1,22 +1,24 @@
 use super::{MessageHandlingError, context::HandlerContext};
+use crate::api::gog;
 use crate::api::gog::achievements::Achievement;
-use crate::constants;
 use crate::proto::common_utils::ProtoPayload;
 use crate::proto::{galaxy_protocols_overlay_for_service::*, gog_protocols_pb};
 use chrono::Utc;
 use log::{debug, info, warn};
 use protobuf::{Enum, Message};
+use reqwest::Client;
 
 pub async fn entry_point(
     payload: &ProtoPayload,
     context: &HandlerContext,
+    reqwest_client: &Client,
 ) -> Result<ProtoPayload, MessageHandlingError> {
     debug!("overlay <-> service entry point called");
     let header = &payload.header;
     let message_type: i32 = header.type_().try_into().unwrap();
 
     if message_type == MessageType::ACCESS_TOKEN_REQUEST.value() {
-        access_token(payload, context).await
+        access_token(payload, context, reqwest_client).await
     } else if message_type == MessageType::OVERLAY_INITIALIZATION_NOTIFICATION.value() {
         init_notification(payload).await?;
         Err(MessageHandlingError::ignored())
@@ -71,17 +73,16 @@ pub async fn achievement_notification(
 async fn access_token(
     _payload: &ProtoPayload,
     context: &HandlerContext,
+    reqwest_client: &Client,
 ) -> Result<ProtoPayload, MessageHandlingError> {
-    let tokens = context.token_store();
-
-    let galaxy_access_token = {
-        let tokens = tokens.lock().await;
-        tokens.get(constants::GALAXY_CLIENT_ID).cloned()
-    };
+    // Overlay talks to GOG services on its own, it needs a token that's still valid
+    let galaxy_access_token = gog::users::galaxy_token(context.token_store(), reqwest_client)
+        .await
+        .ok();
 
     let mut res = AccessTokenResponse::new();
     if let Some(token) = galaxy_access_token {
-        res.set_access_token(token.access_token.clone());
+        res.set_access_token(token);
     }
     let payload = res.write_to_bytes().map_err(MessageHandlingError::proto)?;
     let mut header = gog_protocols_pb::Header::new();
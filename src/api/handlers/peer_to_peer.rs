@@ -0,0 +1,83 @@
+use crate::api::structs::{IDType, UserInfo};
+use crate::lobby::{self, LobbyEvent};
+use crate::proto::common_utils::ProtoPayload;
+use crate::proto::galaxy_common_protocols_peer_to_peer::*;
+use crate::proto::gog_protocols_pb::Header;
+use log::warn;
+use protobuf::{Enum, Message};
+use std::sync::Arc;
+
+use super::{MessageHandlingError, context::HandlerContext};
+
+pub async fn entry_point(
+    payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let header = &payload.header;
+
+    let message_type: i32 = header.type_().try_into().unwrap();
+
+    if message_type == MessageType::SEND_PACKET_REQUEST.value() {
+        send_packet(payload, context, user_info).await?;
+    } else {
+        warn!("Unhandled peer to peer message type {}", message_type);
+        return Err(MessageHandlingError::not_implemented());
+    }
+    // Packets are not acknowledged
+    Err(MessageHandlingError::ignored())
+}
+
+async fn send_packet(
+    payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+) -> Result<(), MessageHandlingError> {
+    let request = SendPacketRequest::parse_from_bytes(&payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    let client_id = context.client_id().await.unwrap_or_default();
+    let sender_id = user_info.galaxy_user_id.parse().unwrap();
+    let recipient_id = IDType::parse(request.recipient_id()).inner();
+
+    lobby::send_packet(
+        &client_id,
+        sender_id,
+        recipient_id,
+        request.channel(),
+        request.data().to_vec(),
+    )
+    .await;
+    Ok(())
+}
+
+/// Encode packet received from another peer for the game
+pub fn encode_packet(
+    event: &LobbyEvent,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let LobbyEvent::Packet {
+        sender_id,
+        channel,
+        data,
+    } = event
+    else {
+        return Ok(None);
+    };
+    let mut notification = PacketNotification::new();
+    notification.set_sender_id(IDType::User(*sender_id).value());
+    notification.set_channel(*channel);
+    notification.set_data(data.clone());
+    let data = notification.write_to_bytes()?;
+
+    let mut header = Header::new();
+    header.set_sort(MessageSort::MESSAGE_SORT.value().try_into()?);
+    header.set_type(MessageType::PACKET_NOTIFICATION.value().try_into()?);
+    header.set_size(data.len().try_into()?);
+    let header_buffer = header.write_to_bytes()?;
+    let header_size: u16 = header_buffer.len().try_into()?;
+
+    let mut buffer = Vec::with_capacity(2 + header_buffer.len() + data.len());
+    buffer.extend(header_size.to_be_bytes());
+    buffer.extend(header_buffer);
+    buffer.extend(data);
+    Ok(Some(buffer))
+}
@@ -0,0 +1,526 @@
+use crate::api::gog::friends;
+use crate::api::structs::{IDType, UserInfo};
+use crate::friends as friends_cache;
+use crate::lobby::{self, LobbyError, LobbyEvent, MemberState};
+use crate::proto::common_utils::ProtoPayload;
+use crate::proto::galaxy_common_protocols_peer_to_server::*;
+use crate::proto::gog_protocols_pb::Header;
+use log::{debug, info, warn};
+use protobuf::{Enum, Message};
+use reqwest::Client;
+use std::sync::Arc;
+
+use super::{MessageHandlingError, context::HandlerContext};
+
+pub async fn entry_point(
+    payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+    reqwest_client: &Client,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    debug!("Handling in peer to server");
+    let header = &payload.header;
+
+    let message_type: i32 = header.type_().try_into().unwrap();
+
+    if message_type == MessageType::GET_FRIEND_LIST_REQUEST.value() {
+        get_friend_list(context, user_info, reqwest_client).await
+    } else if message_type == MessageType::GET_USER_INFO_REQUEST.value() {
+        get_user_info(payload, context, user_info, reqwest_client).await
+    } else if message_type == MessageType::GET_PERSONA_STATE_REQUEST.value() {
+        get_persona_state(payload, context, user_info).await
+    } else if message_type == MessageType::SET_RICH_PRESENCE_REQUEST.value() {
+        set_rich_presence(payload, context, user_info, reqwest_client).await
+    } else if message_type == MessageType::DELETE_RICH_PRESENCE_REQUEST.value() {
+        delete_rich_presence(payload, context, user_info, reqwest_client).await
+    } else if message_type == MessageType::CLEAR_RICH_PRESENCE_REQUEST.value() {
+        clear_rich_presence(context, user_info, reqwest_client).await
+    } else if message_type == MessageType::CREATE_LOBBY_REQUEST.value() {
+        create_lobby(payload, context, user_info).await
+    } else if message_type == MessageType::LIST_LOBBIES_REQUEST.value() {
+        list_lobbies(context).await
+    } else if message_type == MessageType::JOIN_LOBBY_REQUEST.value() {
+        join_lobby(payload, context, user_info).await
+    } else if message_type == MessageType::LEAVE_LOBBY_REQUEST.value() {
+        leave_lobby(payload, user_info).await
+    } else if message_type == MessageType::SET_LOBBY_DATA_REQUEST.value() {
+        set_lobby_data(payload, user_info).await
+    } else if message_type == MessageType::SET_LOBBY_MEMBER_DATA_REQUEST.value() {
+        set_lobby_member_data(payload, user_info).await
+    } else if message_type == MessageType::GET_LOBBY_DATA_REQUEST.value() {
+        get_lobby_data(payload).await
+    } else {
+        warn!("Unhandled peer to server message type {}", message_type);
+        Err(MessageHandlingError::not_implemented())
+    }
+}
+
+fn persona_state(online: bool) -> PersonaState {
+    if online {
+        PersonaState::PERSONA_STATE_ONLINE
+    } else {
+        PersonaState::PERSONA_STATE_OFFLINE
+    }
+}
+
+fn user_to_proto(user: &friends::User, persona_state: PersonaState) -> Option<User> {
+    let id: u64 = user.id.parse().ok()?;
+    let mut entry = User::new();
+    entry.set_user_id(IDType::User(id).value());
+    entry.set_user_name(user.username.clone());
+    entry.set_avatar_small(user.avatar.sdk_img_32.clone());
+    entry.set_avatar_medium(user.avatar.sdk_img_64.clone());
+    entry.set_avatar_large(user.avatar.sdk_img_184.clone());
+    entry.set_persona_state(persona_state);
+    Some(entry)
+}
+
+async fn get_friend_list(
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+    reqwest_client: &Client,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let friends = friends_cache::friend_list(
+        context.token_store(),
+        &user_info.galaxy_user_id,
+        reqwest_client,
+        context.is_online().await,
+    )
+    .await;
+
+    let mut response = GetFriendListResponse::new();
+    response.friends = friends
+        .iter()
+        .filter_map(|friend| {
+            let state = persona_state(friends_cache::is_user_online(&friend.id));
+            user_to_proto(friend, state)
+        })
+        .collect();
+
+    let mut header = Header::new();
+    header.set_type(
+        MessageType::GET_FRIEND_LIST_RESPONSE
+            .value()
+            .try_into()
+            .unwrap(),
+    );
+    let payload = response
+        .write_to_bytes()
+        .map_err(MessageHandlingError::proto)?;
+    header.set_size(payload.len().try_into().unwrap());
+
+    Ok(ProtoPayload { header, payload })
+}
+
+async fn get_user_info(
+    proto_payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+    reqwest_client: &Client,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = GetUserInfoRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    let user_ids: Vec<String> = request
+        .user_ids
+        .iter()
+        .map(|id| IDType::parse(*id).inner().to_string())
+        .collect();
+
+    let online = context.is_online().await;
+    let users = friends_cache::users(
+        context.token_store(),
+        &user_info.galaxy_user_id,
+        &user_ids,
+        reqwest_client,
+        online,
+    )
+    .await;
+
+    let mut response = GetUserInfoResponse::new();
+    response.users = users
+        .iter()
+        .filter_map(|user| {
+            let state = if user.id == user_info.galaxy_user_id {
+                persona_state(online)
+            } else {
+                persona_state(friends_cache::is_user_online(&user.id))
+            };
+            user_to_proto(user, state)
+        })
+        .collect();
+
+    let mut header = Header::new();
+    header.set_type(
+        MessageType::GET_USER_INFO_RESPONSE
+            .value()
+            .try_into()
+            .unwrap(),
+    );
+    let payload = response
+        .write_to_bytes()
+        .map_err(MessageHandlingError::proto)?;
+    header.set_size(payload.len().try_into().unwrap());
+
+    Ok(ProtoPayload { header, payload })
+}
+
+async fn get_persona_state(
+    proto_payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = GetPersonaStateRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    let user_id = IDType::parse(request.user_id()).inner().to_string();
+    let online = if user_id == user_info.galaxy_user_id {
+        context.is_online().await
+    } else {
+        friends_cache::is_user_online(&user_id)
+    };
+
+    let mut response_data = GetPersonaStateResponse::new();
+    response_data.set_user_id(request.user_id());
+    response_data.set_persona_state(persona_state(online));
+    response(MessageType::GET_PERSONA_STATE_RESPONSE, response_data)
+}
+
+/// Notify the game about a friend going online or offline, None when the user is not a friend
+pub async fn encode_persona_state_change(
+    user_info: &UserInfo,
+    friend_id: &str,
+    online: bool,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(friend) = friends_cache::cached_friend(&user_info.galaxy_user_id, friend_id).await
+    else {
+        return Ok(None);
+    };
+    let mut notification = PersonaStateChangeNotification::new();
+    notification.user = user_to_proto(&friend, persona_state(online)).into();
+    let data = notification.write_to_bytes()?;
+
+    let mut header = Header::new();
+    header.set_sort(MessageSort::MESSAGE_SORT.value().try_into()?);
+    header.set_type(
+        MessageType::PERSONA_STATE_CHANGE_NOTIFICATION
+            .value()
+            .try_into()?,
+    );
+    header.set_size(data.len().try_into()?);
+    let header_buffer = header.write_to_bytes()?;
+    let header_size: u16 = header_buffer.len().try_into()?;
+
+    let mut buffer = Vec::with_capacity(2 + header_buffer.len() + data.len());
+    buffer.extend(header_size.to_be_bytes());
+    buffer.extend(header_buffer);
+    buffer.extend(data);
+    Ok(Some(buffer))
+}
+
+fn empty_response(message_type: MessageType) -> ProtoPayload {
+    let mut header = Header::new();
+    header.set_type(message_type.value().try_into().unwrap());
+    header.set_size(0);
+    ProtoPayload {
+        header,
+        payload: Vec::new(),
+    }
+}
+
+async fn set_rich_presence(
+    proto_payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+    reqwest_client: &Client,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = SetRichPresenceRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    info!(
+        "Setting rich presence {} to {}",
+        request.key(),
+        request.value()
+    );
+    context
+        .set_rich_presence(request.key().to_owned(), request.value().to_owned())
+        .await;
+    super::publish_presence(context, reqwest_client, &user_info, true).await;
+
+    Ok(empty_response(MessageType::SET_RICH_PRESENCE_RESPONSE))
+}
+
+async fn delete_rich_presence(
+    proto_payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+    reqwest_client: &Client,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = DeleteRichPresenceRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    info!("Deleting rich presence {}", request.key());
+    context.delete_rich_presence(request.key()).await;
+    super::publish_presence(context, reqwest_client, &user_info, true).await;
+
+    Ok(empty_response(MessageType::DELETE_RICH_PRESENCE_RESPONSE))
+}
+
+async fn clear_rich_presence(
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+    reqwest_client: &Client,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    info!("Clearing rich presence");
+    context.clear_rich_presence().await;
+    super::publish_presence(context, reqwest_client, &user_info, true).await;
+
+    Ok(empty_response(MessageType::CLEAR_RICH_PRESENCE_RESPONSE))
+}
+
+fn user_id(user_info: &UserInfo) -> u64 {
+    user_info.galaxy_user_id.parse().unwrap()
+}
+
+fn lobby_id(id: u64) -> u64 {
+    IDType::parse(id).inner()
+}
+
+fn lobby_result(result: Result<(), LobbyError>) -> LobbyResult {
+    match result {
+        Ok(()) => LobbyResult::LOBBY_RESULT_SUCCESS,
+        Err(LobbyError::DoesNotExist) => LobbyResult::LOBBY_RESULT_DOES_NOT_EXIST,
+        Err(LobbyError::IsFull) => LobbyResult::LOBBY_RESULT_IS_FULL,
+        Err(err) => {
+            warn!("Lobby request failed {:?}", err);
+            LobbyResult::LOBBY_RESULT_ERROR
+        }
+    }
+}
+
+fn lobby_data(data: &std::collections::HashMap<String, String>) -> Vec<LobbyData> {
+    data.iter()
+        .map(|(key, value)| {
+            let mut entry = LobbyData::new();
+            entry.set_key(key.clone());
+            entry.set_value(value.clone());
+            entry
+        })
+        .collect()
+}
+
+fn response<M: Message>(
+    message_type: MessageType,
+    message: M,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let mut header = Header::new();
+    header.set_type(message_type.value().try_into().unwrap());
+    let payload = message
+        .write_to_bytes()
+        .map_err(MessageHandlingError::proto)?;
+    header.set_size(payload.len().try_into().unwrap());
+
+    Ok(ProtoPayload { header, payload })
+}
+
+async fn create_lobby(
+    proto_payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = CreateLobbyRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    let client_id = context.client_id().await.unwrap_or_default();
+    let listed = request.lobby_type() != LobbyType::LOBBY_TYPE_PRIVATE;
+
+    let lobby = lobby::create(
+        &client_id,
+        user_id(&user_info),
+        request.max_members(),
+        request.joinable(),
+        listed,
+    )
+    .await;
+
+    let mut response_data = CreateLobbyResponse::new();
+    response_data.set_lobby_id(IDType::Lobby(lobby.id).value());
+    response_data.set_result(LobbyResult::LOBBY_RESULT_SUCCESS);
+    response(MessageType::CREATE_LOBBY_RESPONSE, response_data)
+}
+
+async fn list_lobbies(context: &HandlerContext) -> Result<ProtoPayload, MessageHandlingError> {
+    let client_id = context.client_id().await.unwrap_or_default();
+
+    let mut response_data = ListLobbiesResponse::new();
+    response_data.lobbies = lobby::list(&client_id)
+        .iter()
+        .map(|lobby| {
+            let mut entry = list_lobbies_response::Lobby::new();
+            entry.set_lobby_id(IDType::Lobby(lobby.id).value());
+            entry.set_owner_id(IDType::User(lobby.owner).value());
+            entry.set_max_members(lobby.max_members);
+            entry.set_member_count(lobby.members.len().try_into().unwrap_or(u32::MAX));
+            entry.data = lobby_data(&lobby.data);
+            entry
+        })
+        .collect();
+    response(MessageType::LIST_LOBBIES_RESPONSE, response_data)
+}
+
+async fn join_lobby(
+    proto_payload: &ProtoPayload,
+    context: &HandlerContext,
+    user_info: Arc<UserInfo>,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = JoinLobbyRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+    let client_id = context.client_id().await.unwrap_or_default();
+
+    let result = lobby::join(
+        &client_id,
+        lobby_id(request.lobby_id()),
+        user_id(&user_info),
+    )
+    .await
+    .map(|_| ());
+
+    let mut response_data = JoinLobbyResponse::new();
+    response_data.set_lobby_id(request.lobby_id());
+    response_data.set_result(lobby_result(result));
+    response(MessageType::JOIN_LOBBY_RESPONSE, response_data)
+}
+
+async fn leave_lobby(
+    proto_payload: &ProtoPayload,
+    user_info: Arc<UserInfo>,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = LeaveLobbyRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+
+    lobby::leave(lobby_id(request.lobby_id()), user_id(&user_info)).await;
+
+    let mut response_data = LeaveLobbyResponse::new();
+    response_data.set_lobby_id(request.lobby_id());
+    response(MessageType::LEAVE_LOBBY_RESPONSE, response_data)
+}
+
+async fn set_lobby_data(
+    proto_payload: &ProtoPayload,
+    user_info: Arc<UserInfo>,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = SetLobbyDataRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+
+    let result = lobby::set_data(
+        lobby_id(request.lobby_id()),
+        user_id(&user_info),
+        request.key().to_owned(),
+        request.value().to_owned(),
+    )
+    .await;
+
+    let mut response_data = SetLobbyDataResponse::new();
+    response_data.set_lobby_id(request.lobby_id());
+    response_data.set_result(lobby_result(result));
+    response(MessageType::SET_LOBBY_DATA_RESPONSE, response_data)
+}
+
+async fn set_lobby_member_data(
+    proto_payload: &ProtoPayload,
+    user_info: Arc<UserInfo>,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = SetLobbyMemberDataRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+
+    let result = lobby::set_member_data(
+        lobby_id(request.lobby_id()),
+        user_id(&user_info),
+        request.key().to_owned(),
+        request.value().to_owned(),
+    )
+    .await;
+
+    let mut response_data = SetLobbyMemberDataResponse::new();
+    response_data.set_lobby_id(request.lobby_id());
+    response_data.set_result(lobby_result(result));
+    response(MessageType::SET_LOBBY_MEMBER_DATA_RESPONSE, response_data)
+}
+
+async fn get_lobby_data(
+    proto_payload: &ProtoPayload,
+) -> Result<ProtoPayload, MessageHandlingError> {
+    let request = GetLobbyDataRequest::parse_from_bytes(&proto_payload.payload)
+        .map_err(MessageHandlingError::proto)?;
+
+    let mut response_data = GetLobbyDataResponse::new();
+    response_data.set_lobby_id(request.lobby_id());
+    match lobby::get(lobby_id(request.lobby_id())) {
+        Some(lobby) => {
+            response_data.set_result(LobbyResult::LOBBY_RESULT_SUCCESS);
+            response_data.set_owner_id(IDType::User(lobby.owner).value());
+            response_data.set_max_members(lobby.max_members);
+            response_data.data = lobby_data(&lobby.data);
+            response_data.members = lobby
+                .members
+                .iter()
+                .map(|member| {
+                    let mut entry = LobbyMember::new();
+                    entry.set_member_id(IDType::User(member.user_id).value());
+                    entry.data = lobby_data(&member.data);
+                    entry
+                })
+                .collect();
+        }
+        None => response_data.set_result(LobbyResult::LOBBY_RESULT_DOES_NOT_EXIST),
+    }
+    response(MessageType::GET_LOBBY_DATA_RESPONSE, response_data)
+}
+
+/// Encode lobby membership and data changes as notifications for the game
+pub fn encode_lobby_event(
+    event: &LobbyEvent,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let (message_type, data) = match event {
+        LobbyEvent::MemberState {
+            lobby_id,
+            member_id,
+            state,
+        } => {
+            let mut notification = LobbyMemberStateNotification::new();
+            notification.set_lobby_id(IDType::Lobby(*lobby_id).value());
+            notification.set_member_id(IDType::User(*member_id).value());
+            notification.set_state(match state {
+                MemberState::Entered => LobbyMemberState::LOBBY_MEMBER_STATE_ENTERED,
+                MemberState::Left => LobbyMemberState::LOBBY_MEMBER_STATE_LEFT,
+            });
+            (
+                MessageType::LOBBY_MEMBER_STATE_NOTIFICATION,
+                notification.write_to_bytes()?,
+            )
+        }
+        LobbyEvent::DataUpdated {
+            lobby_id,
+            member_id,
+        } => {
+            let mut notification = LobbyDataUpdatedNotification::new();
+            notification.set_lobby_id(IDType::Lobby(*lobby_id).value());
+            if let Some(member_id) = member_id {
+                notification.set_member_id(IDType::User(*member_id).value());
+            }
+            (
+                MessageType::LOBBY_DATA_UPDATED_NOTIFICATION,
+                notification.write_to_bytes()?,
+            )
+        }
+        LobbyEvent::Packet { .. } => return Ok(None),
+    };
+
+    let mut header = Header::new();
+    header.set_sort(MessageSort::MESSAGE_SORT.value().try_into()?);
+    header.set_type(message_type.value().try_into()?);
+    header.set_size(data.len().try_into()?);
+    let header_buffer = header.write_to_bytes()?;
+    let header_size: u16 = header_buffer.len().try_into()?;
+
+    let mut buffer = Vec::with_capacity(2 + header_buffer.len() + data.len());
+    buffer.extend(header_size.to_be_bytes());
+    buffer.extend(header_buffer);
+    buffer.extend(data);
+    Ok(Some(buffer))
+}
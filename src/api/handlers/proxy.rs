@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use log::{error, info, warn};
+use protobuf::Enum;
+use reqwest::Client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use super::context::HandlerContext;
+use super::error::MessageHandlingErrorKind;
+use crate::api::structs::UserInfo;
+use crate::decode::{self, OutputFormat};
+use crate::proto::common_utils::ProtoPayload;
+use crate::proto::galaxy_protocols_communication_service::MessageType;
+
+#[derive(Clone, Debug)]
+pub struct ProxyOptions {
+    /// Address of the upstream Galaxy communication service
+    pub upstream: String,
+    /// Respond to messages comet implements instead of forwarding them
+    pub answer_locally: bool,
+}
+
+/// Connection to the upstream communication service for a single game connection
+pub struct Upstream {
+    reader: OwnedReadHalf,
+    writer: OwnedWriteHalf,
+    answer_locally: bool,
+    // oseq of requests answered by comet, upstream responses for them are dropped
+    local_oseqs: HashSet<u32>,
+}
+
+fn is_auth_request(payload: &ProtoPayload) -> bool {
+    payload.header.sort() == 1
+        && payload.header.type_() == MessageType::AUTH_INFO_REQUEST.value() as u32
+}
+
+impl Upstream {
+    pub async fn connect(options: &ProxyOptions) -> Result<Self, tokio::io::Error> {
+        let stream = TcpStream::connect(&options.upstream).await?;
+        info!("Proxying to upstream {}", options.upstream);
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader,
+            writer,
+            answer_locally: options.answer_locally,
+            local_oseqs: HashSet::new(),
+        })
+    }
+
+    pub async fn read_u16(&mut self) -> Result<u16, tokio::io::Error> {
+        self.reader.read_u16().await
+    }
+
+    async fn forward(&mut self, payload: &ProtoPayload) -> Result<(), tokio::io::Error> {
+        info!(
+            "game -> upstream {}",
+            decode::decode(payload, OutputFormat::Text)
+        );
+        let frame = payload.frame().map_err(tokio::io::Error::other)?;
+        self.writer.write_all(&frame).await
+    }
+
+    /// Forward frame received from the game, or answer it when comet implements it
+    pub async fn handle_game_frame(
+        &mut self,
+        context: &HandlerContext,
+        user_info: Arc<UserInfo>,
+        reqwest_client: &Client,
+        payload: ProtoPayload,
+    ) -> Result<(), tokio::io::Error> {
+        if !self.answer_locally {
+            return self.forward(&payload).await;
+        }
+
+        // Upstream needs to know about the game even when comet handles the session
+        let forwarded = is_auth_request(&payload);
+        if forwarded {
+            if payload.header.has_oseq() {
+                self.local_oseqs.insert(payload.header.oseq());
+            }
+            self.forward(&payload).await?;
+        }
+
+        let local_copy = ProtoPayload {
+            header: payload.header.clone(),
+            payload: payload.payload.clone(),
+        };
+        match super::handle_message(context, user_info, reqwest_client, local_copy).await {
+            Ok(res) => {
+                if let Some(response) = ProtoPayload::from_frame(&res) {
+                    info!(
+                        "comet -> game {}",
+                        decode::decode(&response, OutputFormat::Text)
+                    );
+                }
+                context.socket_write(&res).await
+            }
+            Err(err) => match err.kind {
+                MessageHandlingErrorKind::NotImplemented if !forwarded => {
+                    self.forward(&payload).await
+                }
+                MessageHandlingErrorKind::NotImplemented | MessageHandlingErrorKind::Ignored => {
+                    Ok(())
+                }
+                MessageHandlingErrorKind::Unauthorized => {
+                    let _ = context.socket_mut().await.shutdown().await;
+                    Err(tokio::io::Error::new(
+                        tokio::io::ErrorKind::PermissionDenied,
+                        "Game is not authorized",
+                    ))
+                }
+                _ => {
+                    error!("There was an error when handling the message {:?}", err);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Pass the upstream frame to the game, header size was already read with [`Upstream::read_u16`]
+    pub async fn handle_upstream_frame(
+        &mut self,
+        context: &HandlerContext,
+        header_size: u16,
+    ) -> Result<(), tokio::io::Error> {
+        let payload = super::utils::parse_payload(header_size, &mut self.reader).await?;
+        if let Some(oseq) = payload.response_oseq()
+            && self.local_oseqs.remove(&oseq)
+        {
+            warn!(
+                "Dropping upstream response already answered by comet {}",
+                decode::describe(&payload)
+            );
+            return Ok(());
+        }
+
+        info!(
+            "upstream -> game {}",
+            decode::decode(&payload, OutputFormat::Text)
+        );
+        let frame = payload.frame().map_err(tokio::io::Error::other)?;
+        context.socket_write(&frame).await
+    }
+}
@@ -6,6 +6,7 @@ use crate::api::handlers::context::HandlerContext;
 use crate::api::handlers::error::{MessageHandlingError, MessageHandlingErrorKind};
 use crate::api::structs::IDType;
 use crate::db;
+use crate::hooks::{self, HookEvent};
 use base64::prelude::*;
 use log::warn;
 use protobuf::{Enum, Message};
@@ -200,8 +201,12 @@ pub async fn unlock_achievement(
 
     context.set_updated_achievements(true).await;
     achievement.date_unlocked = timestamp_string;
+    hooks::trigger(HookEvent::AchievementUnlocked {
+        client_id: context.client_id().await.unwrap_or_default(),
+        achievement: achievement.clone(),
+    });
     let pid = context.get_pid().await;
-    let _ = context
-        .overlay_sender()
-        .send((pid, OverlayPeerMessage::Achievement(achievement.clone())));
+    context
+        .overlay_registry()
+        .send(pid, OverlayPeerMessage::Achievement(achievement.clone()));
 }
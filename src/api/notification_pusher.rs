@@ -10,6 +10,9 @@ use tokio_tungstenite::{
 };
 use tokio_util::sync::CancellationToken;
 
+use crate::api::gog::users;
+use crate::capture::{self, Direction, Recorder};
+use crate::constants::TokenStorage;
 use crate::proto::common_utils::ProtoPayload;
 use crate::proto::galaxy_protocols_webbroker_service::MessageFromTopic;
 use crate::proto::gog_protocols_pb::response::Status;
@@ -43,32 +46,40 @@ pub enum PusherEvent {
 pub struct NotificationPusherClient {
     pusher_connection: WebSocketStream<MaybeTlsStream<TcpStream>>,
     access_token: String,
+    token_store: TokenStorage,
+    reqwest_client: reqwest::Client,
     topic_sender: Sender<PusherEvent>,
     shutdown_token: CancellationToken,
+    recorder: Option<Recorder>,
 }
 
 impl NotificationPusherClient {
     pub async fn new(
         access_token: &String,
+        token_store: TokenStorage,
+        reqwest_client: reqwest::Client,
         topic_sender: Sender<PusherEvent>,
         shutdown_token: CancellationToken,
+        recorder: Option<Recorder>,
     ) -> NotificationPusherClient {
         debug!("Notification pusher init");
-        let mut retries = 5;
+        let runtime = &crate::CONFIG.get().runtime;
+        let mut retries = runtime.pusher_retries;
         let ws_stream = loop {
-            let stream = NotificationPusherClient::init_connection(access_token).await;
+            let stream =
+                NotificationPusherClient::init_connection(access_token, recorder.as_ref()).await;
             match stream {
                 Ok(stream) => break Some(stream),
                 Err(tungstenite::Error::Io(_err)) => {
                     tokio::select! {
-                        _ = time::sleep(time::Duration::from_secs(10)) => {},
+                        _ = time::sleep(time::Duration::from_secs(runtime.pusher_offline_delay)) => {},
                         _ = shutdown_token.cancelled() => { break None }
                     }
                 }
                 Err(err) => {
                     if retries > 0 {
                         tokio::select! {
-                            _ = time::sleep(time::Duration::from_secs(3)) => {},
+                            _ = time::sleep(time::Duration::from_secs(runtime.pusher_retry_delay)) => {},
                             _ = shutdown_token.cancelled() => { break None }
                         }
                         retries -= 1;
@@ -82,13 +93,17 @@ impl NotificationPusherClient {
         NotificationPusherClient {
             pusher_connection: ws_stream.expect("Unable to get notification pusher connection"),
             access_token: access_token.clone(),
+            token_store,
+            reqwest_client,
             topic_sender,
             shutdown_token,
+            recorder,
         }
     }
 
     async fn init_connection(
         access_token: &String,
+        recorder: Option<&Recorder>,
     ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, tungstenite::Error> {
         let tls_connector = tokio_tungstenite::Connector::Rustls(TLS_CONFIG.clone());
         let (mut ws_stream, _) = connect_async_tls_with_config(
@@ -121,6 +136,7 @@ impl NotificationPusherClient {
         buffer.extend(header_data);
         buffer.extend(request_body);
 
+        capture::record(recorder, Direction::Out, &buffer).await;
         let message = tungstenite::Message::Binary(buffer);
 
         ws_stream.send(message).await?;
@@ -141,7 +157,7 @@ impl NotificationPusherClient {
                         }
                         msg.unwrap()
                     }
-                    _ = time::sleep(time::Duration::from_secs(30)) => {
+                    _ = time::sleep(time::Duration::from_secs(crate::CONFIG.get().runtime.pusher_ping_interval)) => {
                         if pending_ping {
                             // Send offline status to contexts
                             if let Err(err) = self.topic_sender.send(PusherEvent::Offline) {
@@ -194,6 +210,7 @@ impl NotificationPusherClient {
                 debug!("Received a message");
                 if message.is_binary() {
                     let msg_data = message.into_data();
+                    capture::record(self.recorder.as_ref(), Direction::In, &msg_data).await;
                     let proto_message = NotificationPusherClient::parse_message(&msg_data);
                     let parsed_message = match proto_message {
                         Ok(message) => message,
@@ -264,6 +281,12 @@ impl NotificationPusherClient {
                                     message_buffer.extend(header_buf);
                                     message_buffer.extend(payload);
 
+                                    capture::record(
+                                        self.recorder.as_ref(),
+                                        Direction::Out,
+                                        &message_buffer,
+                                    )
+                                    .await;
                                     let new_message = tungstenite::Message::Binary(message_buffer);
                                     if let Err(error) =
                                         self.pusher_connection.feed(new_message).await
@@ -324,18 +347,31 @@ impl NotificationPusherClient {
                 }
             }
             if !self.shutdown_token.is_cancelled() {
-                tokio::time::sleep(time::Duration::from_secs(5)).await;
-                let mut retries = 5;
+                let runtime = &crate::CONFIG.get().runtime;
+                tokio::time::sleep(time::Duration::from_secs(runtime.pusher_reconnect_delay)).await;
+                let mut retries = runtime.pusher_retries;
                 let connection = loop {
                     if self.shutdown_token.is_cancelled() {
                         break None;
                     }
-                    let stream =
-                        NotificationPusherClient::init_connection(&self.access_token).await;
+                    // Token from startup expires after an hour
+                    if let Ok(access_token) =
+                        users::galaxy_token(&self.token_store, &self.reqwest_client).await
+                    {
+                        self.access_token = access_token;
+                    }
+                    let stream = NotificationPusherClient::init_connection(
+                        &self.access_token,
+                        self.recorder.as_ref(),
+                    )
+                    .await;
                     if let Ok(stream) = stream {
                         break Some(stream);
                     } else if retries > 0 {
-                        tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+                        tokio::time::sleep(tokio::time::Duration::from_secs(
+                            runtime.pusher_retry_delay,
+                        ))
+                        .await;
                         retries -= 1;
                     }
                 };
@@ -0,0 +1,102 @@
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+static CAPTURE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Frame received by comet
+    In,
+    /// Frame sent by comet
+    Out,
+}
+
+/// Single line of a capture file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CaptureRecord {
+    /// Unix timestamp in milliseconds
+    pub time: i64,
+    pub direction: Direction,
+    /// Base64 encoded frame - header size, header and payload
+    pub frame: String,
+}
+
+impl CaptureRecord {
+    pub fn frame(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        BASE64_STANDARD.decode(&self.frame)
+    }
+}
+
+pub struct Recorder {
+    file: Mutex<fs::File>,
+}
+
+impl Recorder {
+    pub async fn create(directory: &Path, channel: &str) -> Result<Self, tokio::io::Error> {
+        fs::create_dir_all(directory).await?;
+        let file_name = format!(
+            "{}-{}-{}.jsonl",
+            chrono::Local::now().format("%Y%m%d-%H%M%S"),
+            channel,
+            CAPTURE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = directory.join(file_name);
+        log::info!("Recording {} traffic to {:?}", channel, path);
+        let file = fs::File::create(path).await?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub async fn record(&self, direction: Direction, frame: &[u8]) {
+        let record = CaptureRecord {
+            time: chrono::Utc::now().timestamp_millis(),
+            direction,
+            frame: BASE64_STANDARD.encode(frame),
+        };
+        let mut line = serde_json::to_vec(&record).expect("Failed to serialize capture record");
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        if let Err(err) = file.write_all(&line).await {
+            log::warn!("Failed to write capture record {err}");
+            return;
+        }
+        let _ = file.flush().await;
+    }
+}
+
+/// Create a recorder for the channel when recording is enabled
+pub async fn recorder_for(directory: Option<&Path>, channel: &str) -> Option<Recorder> {
+    let directory = directory?;
+    match Recorder::create(directory, channel).await {
+        Ok(recorder) => Some(recorder),
+        Err(err) => {
+            log::error!("Failed to create capture file for {channel} {err}");
+            None
+        }
+    }
+}
+
+pub async fn record(recorder: Option<&Recorder>, direction: Direction, frame: &[u8]) {
+    if let Some(recorder) = recorder {
+        recorder.record(direction, frame).await
+    }
+}
+
+pub async fn read_capture(
+    path: &Path,
+) -> Result<Vec<CaptureRecord>, Box<dyn std::error::Error + Send + Sync>> {
+    let data = fs::read_to_string(path).await?;
+    let mut records = Vec::new();
+    for line in data.lines().filter(|line| !line.trim().is_empty()) {
+        records.push(serde_json::from_str(line)?);
+    }
+    Ok(records)
+}
@@ -0,0 +1,570 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use md5::{Digest, Md5};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::api::gog::cloudstorage::{self, RemoteFile};
+use crate::constants::TokenStorage;
+use crate::paths;
+
+/// What to do when a file changed both locally and in the cloud since the last sync
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictResolution {
+    /// Leave both versions untouched and report the conflict
+    #[default]
+    Skip,
+    /// Keep the most recently modified version
+    Newest,
+    Local,
+    Remote,
+}
+
+// Hashes of the file at the time of the last sync
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct FileState {
+    local_hash: String,
+    remote_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct SyncState {
+    /// Keyed by the file name in the cloud, `<location>/<path>`
+    files: HashMap<String, FileState>,
+}
+
+#[derive(Default, Debug)]
+pub struct SyncSummary {
+    pub uploaded: u32,
+    pub downloaded: u32,
+    pub deleted_local: u32,
+    pub deleted_remote: u32,
+    pub unchanged: u32,
+    pub conflicts: Vec<String>,
+}
+
+impl std::fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "uploaded {}, downloaded {}, deleted locally {}, deleted remotely {}, unchanged {}, conflicts {}",
+            self.uploaded,
+            self.downloaded,
+            self.deleted_local,
+            self.deleted_remote,
+            self.unchanged,
+            self.conflicts.len()
+        )
+    }
+}
+
+struct LocalFile {
+    path: PathBuf,
+    hash: String,
+    modified: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    None,
+    Upload,
+    Download,
+    DeleteLocal,
+    DeleteRemote,
+    Conflict,
+}
+
+fn state_path(client_id: &str, user_id: &str) -> PathBuf {
+    paths::GAMEPLAY_STORAGE
+        .join(client_id)
+        .join(user_id)
+        .join("cloud_sync.json")
+}
+
+async fn load_state(path: &Path) -> SyncState {
+    match tokio::fs::read(path).await {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+            warn!("Invalid cloud sync state, starting over {err}");
+            SyncState::default()
+        }),
+        Err(_) => SyncState::default(),
+    }
+}
+
+// Downloads are written next to the save and renamed, so an interrupted sync leaves no half
+// written saves behind
+const DOWNLOAD_SUFFIX: &str = ".comet-download";
+
+fn hash(data: &[u8]) -> String {
+    format!("{:x}", Md5::digest(data))
+}
+
+// Every file under the directory keyed by its path relative to it, with / as separator
+fn list_local(root: &Path) -> std::io::Result<HashMap<String, LocalFile>> {
+    let mut files = HashMap::new();
+    let mut directories = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                directories.push(path);
+                continue;
+            }
+            if path.to_string_lossy().ends_with(DOWNLOAD_SUFFIX) {
+                continue;
+            }
+            let relative = path
+                .strip_prefix(root)
+                .unwrap()
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let data = std::fs::read(&path)?;
+            let modified = std::fs::metadata(&path)?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            files.insert(
+                relative,
+                LocalFile {
+                    hash: hash(&data),
+                    modified: modified.into(),
+                    path,
+                },
+            );
+        }
+    }
+    Ok(files)
+}
+
+fn decide(
+    local: Option<&LocalFile>,
+    remote: Option<&RemoteFile>,
+    state: Option<&FileState>,
+    resolution: ConflictResolution,
+) -> Action {
+    match (local, remote) {
+        (Some(local), Some(remote)) => {
+            if local.hash == remote.hash {
+                return Action::None;
+            }
+            let local_changed = state.is_none_or(|state| state.local_hash != local.hash);
+            let remote_changed = state.is_none_or(|state| state.remote_hash != remote.hash);
+            match (local_changed, remote_changed) {
+                (false, false) => Action::None,
+                (true, false) => Action::Upload,
+                (false, true) => Action::Download,
+                (true, true) => match resolution {
+                    ConflictResolution::Skip => Action::Conflict,
+                    ConflictResolution::Local => Action::Upload,
+                    ConflictResolution::Remote => Action::Download,
+                    ConflictResolution::Newest => {
+                        match DateTime::parse_from_rfc3339(&remote.last_modified) {
+                            Ok(remote_time) if remote_time > local.modified => Action::Download,
+                            Ok(_) => Action::Upload,
+                            Err(_) => Action::Conflict,
+                        }
+                    }
+                },
+            }
+        }
+        // Removed in the cloud when it was already synced and not modified since
+        (Some(local), None) => match state {
+            Some(state) if state.local_hash == local.hash => Action::DeleteLocal,
+            _ => Action::Upload,
+        },
+        (None, Some(remote)) => match state {
+            Some(state) if state.remote_hash == remote.hash => Action::DeleteRemote,
+            _ => Action::Download,
+        },
+        (None, None) => Action::None,
+    }
+}
+
+// Action for every file on either side, sorted by name. When one side lists no files while the
+// state has synced files of the location, the listing is not trusted to mean they were deleted
+fn plan(
+    local_files: &HashMap<String, LocalFile>,
+    remote_files: &HashMap<String, RemoteFile>,
+    state: &SyncState,
+    prefix: &str,
+    resolution: ConflictResolution,
+) -> Vec<(String, Action)> {
+    let synced_before = state.files.keys().any(|key| key.starts_with(prefix));
+    let keep_remote = local_files.is_empty() && synced_before;
+    if keep_remote {
+        warn!("Save directory is empty, cloud saves will be downloaded instead of deleted");
+    }
+    let keep_local = remote_files.is_empty() && synced_before;
+    if keep_local {
+        warn!("No saves in the cloud, local saves will be uploaded instead of deleted");
+    }
+
+    let names: HashSet<&String> = remote_files.keys().chain(local_files.keys()).collect();
+    let mut names: Vec<&String> = names.into_iter().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| {
+            let local = local_files.get(name);
+            let remote = remote_files.get(name);
+            let file_state = state.files.get(&format!("{prefix}{name}"));
+            let action = match decide(local, remote, file_state, resolution) {
+                Action::DeleteRemote if keep_remote => Action::Download,
+                Action::DeleteLocal if keep_local => Action::Upload,
+                action => action,
+            };
+            (name.clone(), action)
+        })
+        .collect()
+}
+
+/// Synchronize the directory with the save location in GOG cloud storage.
+/// Token for the client has to be present in the token store
+pub async fn sync(
+    token_store: &TokenStorage,
+    client_id: &str,
+    user_id: &str,
+    reqwest_client: &Client,
+    location: &str,
+    directory: &Path,
+    resolution: ConflictResolution,
+) -> Result<SyncSummary, Box<dyn std::error::Error + Send + Sync>> {
+    info!("Syncing {location} cloud saves of {client_id} with {directory:?}");
+    // Missing directory would look like every save was deleted
+    if !directory.is_dir() {
+        return Err(format!("Save directory {directory:?} does not exist").into());
+    }
+    let state_file = state_path(client_id, user_id);
+    let mut state = load_state(&state_file).await;
+    let mut summary = SyncSummary::default();
+
+    let prefix = format!("{location}/");
+    let remote_files: HashMap<String, RemoteFile> =
+        cloudstorage::list_files(token_store, client_id, user_id, reqwest_client)
+            .await?
+            .into_iter()
+            .filter_map(|file| {
+                let name = file.name.strip_prefix(&prefix)?.to_owned();
+                // Names come from the server, they must not point outside the directory
+                if name.is_empty()
+                    || !Path::new(&name)
+                        .components()
+                        .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    warn!("Ignoring cloud file with invalid name {:?}", file.name);
+                    return None;
+                }
+                Some((name, file))
+            })
+            .collect();
+    let directory_clone = directory.to_path_buf();
+    let local_files = tokio::task::spawn_blocking(move || list_local(&directory_clone)).await??;
+
+    for (name, action) in plan(&local_files, &remote_files, &state, &prefix, resolution) {
+        let local = local_files.get(&name);
+        let remote = remote_files.get(&name);
+        let remote_name = format!("{prefix}{name}");
+
+        match action {
+            Action::None => {
+                if let (Some(local), Some(remote)) = (local, remote) {
+                    let file_state = FileState {
+                        local_hash: local.hash.clone(),
+                        remote_hash: remote.hash.clone(),
+                    };
+                    state.files.insert(remote_name.clone(), file_state);
+                }
+                summary.unchanged += 1;
+            }
+            Action::Upload => {
+                let local = local.unwrap();
+                let data = tokio::fs::read(&local.path).await?;
+                cloudstorage::upload_file(
+                    token_store,
+                    client_id,
+                    user_id,
+                    &remote_name,
+                    data,
+                    &local.hash,
+                    &local.modified.to_rfc3339(),
+                    reqwest_client,
+                )
+                .await?;
+                info!("Uploaded {name}");
+                let file_state = FileState {
+                    local_hash: local.hash.clone(),
+                    remote_hash: local.hash.clone(),
+                };
+                state.files.insert(remote_name.clone(), file_state);
+                summary.uploaded += 1;
+            }
+            Action::Download => {
+                let remote = remote.unwrap();
+                let data = cloudstorage::download_file(
+                    token_store,
+                    client_id,
+                    user_id,
+                    &remote_name,
+                    reqwest_client,
+                )
+                .await?;
+                let path = directory.join(&name);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+                temp_name.push(DOWNLOAD_SUFFIX);
+                let temp_path = path.with_file_name(temp_name);
+                tokio::fs::write(&temp_path, &data).await?;
+                // Keep modification time in line with the cloud for later comparisons
+                if let Ok(time) = DateTime::parse_from_rfc3339(&remote.last_modified) {
+                    let file = std::fs::File::options().write(true).open(&temp_path)?;
+                    let _ = file.set_modified(SystemTime::from(time));
+                }
+                tokio::fs::rename(&temp_path, &path).await?;
+                info!("Downloaded {name}");
+                let file_state = FileState {
+                    local_hash: hash(&data),
+                    remote_hash: remote.hash.clone(),
+                };
+                state.files.insert(remote_name.clone(), file_state);
+                summary.downloaded += 1;
+            }
+            Action::DeleteLocal => {
+                tokio::fs::remove_file(&local.unwrap().path).await?;
+                info!("Removed {name}, it was deleted from the cloud");
+                state.files.remove(&remote_name);
+                summary.deleted_local += 1;
+            }
+            Action::DeleteRemote => {
+                cloudstorage::delete_file(
+                    token_store,
+                    client_id,
+                    user_id,
+                    &remote_name,
+                    reqwest_client,
+                )
+                .await?;
+                info!("Removed {name} from the cloud, it was deleted locally");
+                state.files.remove(&remote_name);
+                summary.deleted_remote += 1;
+            }
+            Action::Conflict => {
+                warn!("{name} was modified both locally and in the cloud, skipping");
+                summary.conflicts.push(name.clone());
+            }
+        }
+    }
+
+    if let Some(parent) = state_file.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&state_file, serde_json::to_vec_pretty(&state)?).await?;
+    info!("Cloud sync finished: {summary}");
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(hash: &str) -> LocalFile {
+        LocalFile {
+            path: PathBuf::from(hash),
+            hash: hash.to_owned(),
+            modified: DateTime::parse_from_rfc3339("2024-01-01T12:00:00Z")
+                .unwrap()
+                .into(),
+        }
+    }
+
+    fn remote(hash: &str, last_modified: &str) -> RemoteFile {
+        RemoteFile {
+            name: String::new(),
+            hash: hash.to_owned(),
+            last_modified: last_modified.to_owned(),
+            bytes: 0,
+        }
+    }
+
+    fn synced(local_hash: &str, remote_hash: &str) -> FileState {
+        FileState {
+            local_hash: local_hash.to_owned(),
+            remote_hash: remote_hash.to_owned(),
+        }
+    }
+
+    const OLDER: &str = "2024-01-01T10:00:00Z";
+    const NEWER: &str = "2024-01-01T14:00:00Z";
+
+    #[test]
+    fn decide_both_sides() {
+        let skip = ConflictResolution::Skip;
+        let state = synced("a", "a");
+        let decide_with = |local_hash, remote_hash, resolution| {
+            decide(
+                Some(&local(local_hash)),
+                Some(&remote(remote_hash, NEWER)),
+                Some(&state),
+                resolution,
+            )
+        };
+        assert_eq!(decide_with("b", "b", skip), Action::None);
+        assert_eq!(decide_with("a", "a", skip), Action::None);
+        assert_eq!(decide_with("b", "a", skip), Action::Upload);
+        assert_eq!(decide_with("a", "b", skip), Action::Download);
+        assert_eq!(decide_with("b", "c", skip), Action::Conflict);
+        assert_eq!(
+            decide_with("b", "c", ConflictResolution::Local),
+            Action::Upload
+        );
+        assert_eq!(
+            decide_with("b", "c", ConflictResolution::Remote),
+            Action::Download
+        );
+    }
+
+    #[test]
+    fn decide_newest() {
+        let newest = ConflictResolution::Newest;
+        let decide_with = |last_modified| {
+            decide(
+                Some(&local("b")),
+                Some(&remote("c", last_modified)),
+                None,
+                newest,
+            )
+        };
+        assert_eq!(decide_with(NEWER), Action::Download);
+        assert_eq!(decide_with(OLDER), Action::Upload);
+        assert_eq!(decide_with("invalid"), Action::Conflict);
+    }
+
+    #[test]
+    fn decide_one_side() {
+        let skip = ConflictResolution::Skip;
+        let file = local("a");
+        assert_eq!(decide(Some(&file), None, None, skip), Action::Upload);
+        assert_eq!(
+            decide(Some(&file), None, Some(&synced("a", "a")), skip),
+            Action::DeleteLocal
+        );
+        // Modified after the last sync, the change is kept
+        assert_eq!(
+            decide(Some(&file), None, Some(&synced("b", "b")), skip),
+            Action::Upload
+        );
+
+        let file = remote("a", OLDER);
+        assert_eq!(decide(None, Some(&file), None, skip), Action::Download);
+        assert_eq!(
+            decide(None, Some(&file), Some(&synced("a", "a")), skip),
+            Action::DeleteRemote
+        );
+        assert_eq!(
+            decide(None, Some(&file), Some(&synced("b", "b")), skip),
+            Action::Download
+        );
+        assert_eq!(decide(None, None, None, skip), Action::None);
+    }
+
+    fn state_of(names: &[&str]) -> SyncState {
+        SyncState {
+            files: names
+                .iter()
+                .map(|name| (name.to_string(), synced("a", "a")))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn plan_empty_local_directory_downloads() {
+        let remote_files = HashMap::from([
+            (String::from("one"), remote("a", OLDER)),
+            (String::from("two"), remote("a", OLDER)),
+        ]);
+        let state = state_of(&["saves/one", "saves/two"]);
+        let actions = plan(
+            &HashMap::new(),
+            &remote_files,
+            &state,
+            "saves/",
+            ConflictResolution::Skip,
+        );
+        assert_eq!(
+            actions,
+            vec![
+                (String::from("one"), Action::Download),
+                (String::from("two"), Action::Download)
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_empty_remote_listing_uploads() {
+        let local_files = HashMap::from([
+            (String::from("one"), local("a")),
+            (String::from("two"), local("a")),
+        ]);
+        let state = state_of(&["saves/one", "saves/two"]);
+        let actions = plan(
+            &local_files,
+            &HashMap::new(),
+            &state,
+            "saves/",
+            ConflictResolution::Skip,
+        );
+        assert_eq!(
+            actions,
+            vec![
+                (String::from("one"), Action::Upload),
+                (String::from("two"), Action::Upload)
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_deletes_single_files() {
+        let local_files = HashMap::from([
+            (String::from("kept"), local("a")),
+            (String::from("removed"), local("a")),
+        ]);
+        let remote_files = HashMap::from([(String::from("kept"), remote("a", OLDER))]);
+        let state = state_of(&["saves/kept", "saves/removed"]);
+        let actions = plan(
+            &local_files,
+            &remote_files,
+            &state,
+            "saves/",
+            ConflictResolution::Skip,
+        );
+        assert_eq!(
+            actions,
+            vec![
+                (String::from("kept"), Action::None),
+                (String::from("removed"), Action::DeleteLocal)
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_guard_is_per_location() {
+        // State of another location doesn't make an empty listing suspicious
+        let remote_files = HashMap::from([(String::from("one"), remote("a", OLDER))]);
+        let state = state_of(&["other/one"]);
+        let actions = plan(
+            &HashMap::new(),
+            &remote_files,
+            &state,
+            "saves/",
+            ConflictResolution::Skip,
+        );
+        assert_eq!(actions, vec![(String::from("one"), Action::Download)]);
+    }
+}
@@ -1,14 +1,342 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
 #[derive(Deserialize, Default, Debug)]
 #[serde(default)]
 pub struct Configuration {
     pub overlay: OverlayConfiguration,
+    pub hooks: HooksConfiguration,
+    pub listen: ListenConfiguration,
+    pub lan: LanConfiguration,
+    pub runtime: RuntimeConfiguration,
+    /// Language used instead of the system one, e.g. "de-DE"
+    pub language: Option<String>,
+    /// Per game overrides keyed by client_id
+    pub games: HashMap<String, GameConfiguration>,
 }
 
+impl Configuration {
+    // Environment overrides and validation, applied to every loaded configuration
+    fn prepare(mut self) -> Self {
+        self.runtime.apply_environment();
+        self.runtime.validate();
+        self
+    }
+
+    /// Settings for the game with global values replaced by its `[games.<client_id>]` table
+    pub fn resolve(&self, client_id: Option<&str>) -> GameSettings {
+        let mut settings = GameSettings {
+            overlay: self.overlay.clone(),
+            offline: false,
+            leaderboard_uploads: true,
+            language: self
+                .language
+                .clone()
+                .unwrap_or_else(|| crate::LOCALE.clone()),
+            workarounds: None,
+            cloud: None,
+            product_id: None,
+        };
+        let Some(game) = client_id.and_then(|client_id| self.games.get(client_id)) else {
+            return settings;
+        };
+
+        if let Some(volume) = game.notification_volume {
+            settings.overlay.notification_volume = volume;
+        }
+        if let Some(position) = &game.position {
+            settings.overlay.position = position.clone();
+        }
+        let notifications = &mut settings.overlay.notifications;
+        for (config, game_config) in [
+            (
+                &mut notifications.achievements,
+                &game.notifications.achievements,
+            ),
+            (&mut notifications.chat, &game.notifications.chat),
+            (
+                &mut notifications.friend_online,
+                &game.notifications.friend_online,
+            ),
+            (
+                &mut notifications.friend_invite,
+                &game.notifications.friend_invite,
+            ),
+            (
+                &mut notifications.friend_game_start,
+                &game.notifications.friend_game_start,
+            ),
+            (
+                &mut notifications.game_invite,
+                &game.notifications.game_invite,
+            ),
+        ] {
+            if let Some(enabled) = game_config.enabled {
+                config.enabled = enabled;
+            }
+            if let Some(sound) = game_config.sound {
+                config.sound = sound;
+            }
+        }
+        settings.offline = game.offline.unwrap_or(false);
+        settings.leaderboard_uploads = !game.disable_leaderboard_uploads.unwrap_or(false);
+        if let Some(language) = &game.language {
+            settings.language = language.clone();
+        }
+        settings.workarounds = game.workarounds.clone();
+        settings.cloud = game.cloud.clone();
+        settings.product_id = game.product_id.clone();
+        settings
+    }
+}
+
+/// Effective configuration of a single game
+#[derive(Clone, Debug)]
+pub struct GameSettings {
+    pub overlay: OverlayConfiguration,
+    /// Never connect to GOG, use only local data
+    pub offline: bool,
+    pub leaderboard_uploads: bool,
+    pub language: String,
+    /// Workarounds file used instead of `<client_id>.progress`
+    pub workarounds: Option<PathBuf>,
+    pub cloud: Option<CloudConfiguration>,
+    /// GOG product id used instead of the detected one
+    pub product_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CloudConfiguration {
+    /// Local directory with the saves
+    pub path: PathBuf,
+    /// Name of the save location in the cloud
+    #[serde(default = "default_cloud_location")]
+    pub location: String,
+    /// Sync when the game starts and exits
+    #[serde(default = "default_true")]
+    pub auto_sync: bool,
+    #[serde(default)]
+    pub conflict: crate::cloud::ConflictResolution,
+}
+
+fn default_cloud_location() -> String {
+    String::from("__default")
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct GameConfiguration {
+    pub notification_volume: Option<u32>,
+    pub position: Option<OverlayPosition>,
+    pub notifications: GameNotifications,
+    pub offline: Option<bool>,
+    pub disable_leaderboard_uploads: Option<bool>,
+    pub language: Option<String>,
+    pub workarounds: Option<PathBuf>,
+    pub cloud: Option<CloudConfiguration>,
+    pub product_id: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct GameNotifications {
+    pub achievements: GameNotificationConfig,
+    pub chat: GameNotificationConfig,
+    pub friend_online: GameNotificationConfig,
+    pub friend_invite: GameNotificationConfig,
+    pub friend_game_start: GameNotificationConfig,
+    pub game_invite: GameNotificationConfig,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct GameNotificationConfig {
+    pub enabled: Option<bool>,
+    pub sound: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ListenConfiguration {
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for ListenConfiguration {
+    fn default() -> Self {
+        Self {
+            address: String::from("127.0.0.1"),
+            port: 9977,
+        }
+    }
+}
+
+/// Discovery of lobbies hosted by other comet instances in the local network
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct LanConfiguration {
+    /// Share lobbies with other comet instances in the network, off by default as
+    /// any host that can reach the port is trusted
+    pub enabled: bool,
+    pub port: u16,
+    pub broadcast_address: String,
+    /// How often hosted lobbies are announced, in seconds
+    pub announce_interval: u64,
+}
+
+impl Default for LanConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 9979,
+            broadcast_address: String::from("255.255.255.255"),
+            announce_interval: 2,
+        }
+    }
+}
+
+/// Timings and retry counts, all durations are in seconds
 #[derive(Deserialize, Debug)]
 #[serde(default)]
+pub struct RuntimeConfiguration {
+    /// How often game progress is synced with GOG
+    pub sync_interval: u64,
+    /// Age after which game and Galaxy tokens are refreshed
+    pub token_refresh_age: u64,
+    /// How long to wait for new connections before quitting with `--quit`
+    pub idle_wait: u64,
+    /// How often components are checked for updates
+    pub component_check_interval: u64,
+    /// Download attempts of the peer component at startup
+    pub component_retries: u64,
+    /// Files of a component downloaded at the same time
+    pub component_download_jobs: u64,
+    /// Directory or archive components are installed from instead of GOG servers
+    pub component_source: Option<PathBuf>,
+    /// How often notifications-pusher connection is pinged
+    pub pusher_ping_interval: u64,
+    /// Connection attempts to notifications-pusher before giving up
+    pub pusher_retries: u32,
+    /// Wait between notifications-pusher connection attempts
+    pub pusher_retry_delay: u64,
+    /// Wait before reconnecting to notifications-pusher after losing the connection
+    pub pusher_reconnect_delay: u64,
+    /// Wait between connection attempts when there is no network
+    pub pusher_offline_delay: u64,
+    /// Age after which cached details of friends and other users are fetched again
+    pub friends_cache_ttl: u64,
+    /// Age after which cached product details shown in the overlay are refreshed
+    pub product_cache_ttl: u64,
+    /// How long the game launch waits for cloud saves to sync
+    pub cloud_sync_timeout: u64,
+}
+
+impl Default for RuntimeConfiguration {
+    fn default() -> Self {
+        Self {
+            sync_interval: 10,
+            token_refresh_age: 3500,
+            idle_wait: 15,
+            component_check_interval: 24 * 3600,
+            component_retries: 10,
+            component_download_jobs: 4,
+            component_source: None,
+            pusher_ping_interval: 30,
+            pusher_retries: 5,
+            pusher_retry_delay: 3,
+            pusher_reconnect_delay: 5,
+            pusher_offline_delay: 10,
+            friends_cache_ttl: 24 * 3600,
+            product_cache_ttl: 7 * 24 * 3600,
+            cloud_sync_timeout: 30,
+        }
+    }
+}
+
+fn env_override<T: std::str::FromStr>(name: &str, value: &mut T) {
+    if let Ok(env_value) = std::env::var(name) {
+        match env_value.parse() {
+            Ok(parsed) => *value = parsed,
+            Err(_) => log::warn!("Ignoring invalid {name} value {env_value:?}"),
+        }
+    }
+}
+
+impl RuntimeConfiguration {
+    fn apply_environment(&mut self) {
+        env_override("COMET_SYNC_INTERVAL", &mut self.sync_interval);
+        env_override("COMET_TOKEN_REFRESH_AGE", &mut self.token_refresh_age);
+        env_override("COMET_IDLE_WAIT", &mut self.idle_wait);
+        env_override(
+            "COMET_COMPONENT_CHECK_INTERVAL",
+            &mut self.component_check_interval,
+        );
+        env_override("COMET_COMPONENT_RETRIES", &mut self.component_retries);
+        env_override(
+            "COMET_COMPONENT_DOWNLOAD_JOBS",
+            &mut self.component_download_jobs,
+        );
+        if let Ok(source) = std::env::var("COMET_COMPONENT_SOURCE") {
+            self.component_source = Some(PathBuf::from(source));
+        }
+        env_override("COMET_PUSHER_PING_INTERVAL", &mut self.pusher_ping_interval);
+        env_override("COMET_PUSHER_RETRIES", &mut self.pusher_retries);
+        env_override("COMET_PUSHER_RETRY_DELAY", &mut self.pusher_retry_delay);
+        env_override(
+            "COMET_PUSHER_RECONNECT_DELAY",
+            &mut self.pusher_reconnect_delay,
+        );
+        env_override("COMET_PUSHER_OFFLINE_DELAY", &mut self.pusher_offline_delay);
+        env_override("COMET_FRIENDS_CACHE_TTL", &mut self.friends_cache_ttl);
+        env_override("COMET_PRODUCT_CACHE_TTL", &mut self.product_cache_ttl);
+        env_override("COMET_CLOUD_SYNC_TIMEOUT", &mut self.cloud_sync_timeout);
+    }
+
+    fn validate(&mut self) {
+        let defaults = Self::default();
+        for (name, value, default) in [
+            (
+                "sync_interval",
+                &mut self.sync_interval,
+                defaults.sync_interval,
+            ),
+            (
+                "component_download_jobs",
+                &mut self.component_download_jobs,
+                defaults.component_download_jobs,
+            ),
+            (
+                "pusher_ping_interval",
+                &mut self.pusher_ping_interval,
+                defaults.pusher_ping_interval,
+            ),
+            (
+                "cloud_sync_timeout",
+                &mut self.cloud_sync_timeout,
+                defaults.cloud_sync_timeout,
+            ),
+        ] {
+            if *value == 0 {
+                log::warn!("runtime.{name} has to be greater than 0, using {default}");
+                *value = default;
+            }
+        }
+        // Tokens are valid for an hour, refresh has to happen before they expire
+        if self.token_refresh_age == 0 || self.token_refresh_age >= 3600 {
+            log::warn!(
+                "runtime.token_refresh_age has to be between 1 and 3599, using {}",
+                defaults.token_refresh_age
+            );
+            self.token_refresh_age = defaults.token_refresh_age;
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct OverlayConfiguration {
     #[serde(default = "default_volume")]
     pub notification_volume: u32,
@@ -26,7 +354,7 @@ impl Default for OverlayConfiguration {
     }
 }
 
-#[derive(Deserialize, Default, Debug)]
+#[derive(Deserialize, Default, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum OverlayPosition {
     #[default]
@@ -47,7 +375,7 @@ impl std::fmt::Display for OverlayPosition {
     }
 }
 
-#[derive(Deserialize, Default, Debug)]
+#[derive(Deserialize, Default, Debug, Clone)]
 #[serde(default)]
 pub struct OverlayNotifications {
     pub achievements: OverlayNotificationConfig,
@@ -58,7 +386,7 @@ pub struct OverlayNotifications {
     pub game_invite: OverlayNotificationConfig,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct OverlayNotificationConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -75,6 +403,17 @@ impl Default for OverlayNotificationConfig {
     }
 }
 
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct HooksConfiguration {
+    pub achievement_unlocked: Option<String>,
+    pub leaderboard_score_posted: Option<String>,
+    pub leaderboard_rank_changed: Option<String>,
+    pub session_start: Option<String>,
+    pub session_end: Option<String>,
+    pub sync_failed: Option<String>,
+}
+
 fn default_true() -> bool {
     true
 }
@@ -87,3 +426,39 @@ pub fn load_config() -> Result<Configuration, Box<dyn std::error::Error + Send +
     let data = fs::read_to_string(crate::paths::CONFIG_FILE.as_path())?;
     Ok(toml::from_str(&data)?)
 }
+
+/// Configuration that can be replaced while comet is running.
+/// Use [`SharedConfiguration::get`] every time the values are needed, so changes are picked up
+pub struct SharedConfiguration {
+    current: RwLock<Arc<Configuration>>,
+}
+
+impl SharedConfiguration {
+    pub fn load() -> Self {
+        Self {
+            current: RwLock::new(Arc::new(load_config().unwrap_or_default().prepare())),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Configuration> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Read the configuration file again, current configuration is kept when the file is invalid.
+    /// A missing file resets the configuration to defaults
+    pub fn reload(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let config = match load_config() {
+            Ok(config) => config,
+            Err(err)
+                if err
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                Configuration::default()
+            }
+            Err(err) => return Err(err),
+        };
+        *self.current.write().unwrap() = Arc::new(config.prepare());
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 pub static GALAXY_CLIENT_ID: &str = "46899977096215655";
+pub static GALAXY_CLIENT_SECRET: &str =
+    "9d85c43b1482497dbbce61f6e4aa173a433796eeae2ca8c5f6129f2dc4de46d9";
 pub static NOTIFICATIONS_PUSHER_SOCKET: &str = "wss://notifications-pusher.gog.com/";
 
 use crate::api::structs::Token;
@@ -18,6 +18,7 @@ CREATE TABLE IF NOT EXISTS `game_info` (`time_played` INTEGER NOT NULL);
 CREATE TABLE IF NOT EXISTS `int_statistic` (`id` INTEGER REFERENCES statistic ( id ) NOT NULL UNIQUE,`value` INTEGER NOT NULL DEFAULT 0,`default_value` INTEGER NOT NULL DEFAULT 0,`min_value` INTEGER,`max_value` INTEGER,`max_change` INTEGER);
 CREATE TABLE IF NOT EXISTS `float_statistic` (`id` INTEGER REFERENCES statistic ( id ) NOT NULL UNIQUE,`value` REAL NOT NULL DEFAULT 0,`default_value` REAL NOT NULL DEFAULT 0,`min_value` REAL,`max_value` REAL,`max_change` REAL,`window` REAL DEFAULT NULL);
 CREATE TABLE IF NOT EXISTS `database_info` (`key` TEXT PRIMARY KEY NOT NULL,`value` TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS `owned_product` (`id` INTEGER PRIMARY KEY NOT NULL);
 "#;
 
 pub async fn setup_connection(client_id: &str, user_id: &str) -> Result<SqlitePool, Error> {
@@ -354,10 +355,21 @@ pub async fn get_achievements(
     Ok((achievements, achievements_mode))
 }
 
+/// Language of stored achievement names and descriptions
+pub async fn get_achievements_language(database: &SqlitePool) -> Option<String> {
+    let mut connection = database.acquire().await.ok()?;
+    let res = sqlx::query("SELECT * FROM database_info WHERE key='language'")
+        .fetch_one(&mut *connection)
+        .await
+        .ok()?;
+    res.try_get("value").ok()
+}
+
 pub async fn set_achievements(
     database: SqlitePool,
     achievements: &Vec<Achievement>,
     mode: &str,
+    language: &str,
 ) -> Result<(), Error> {
     let mut connection = database.acquire().await?;
     let mut transaction = connection.begin().await?;
@@ -399,10 +411,11 @@ pub async fn set_achievements(
 
     sqlx::query(
         r"INSERT INTO database_info VALUES 
-          ('achievements_retrieved', '1'), ('achievements_mode', $1)
+          ('achievements_retrieved', '1'), ('achievements_mode', $1), ('language', $2)
           ON CONFLICT(key) DO UPDATE SET value=excluded.value",
     )
     .bind(mode)
+    .bind(language)
     .execute(&mut *transaction)
     .await?;
 
@@ -477,6 +490,62 @@ pub async fn reset_achievements(context: &HandlerContext) -> Result<(), Error> {
     Ok(())
 }
 
+pub async fn has_owned_products(database: &SqlitePool) -> bool {
+    let connection = database.acquire().await;
+    if connection.is_err() {
+        return false;
+    }
+    let mut connection = connection.unwrap();
+    let res = sqlx::query("SELECT * FROM database_info WHERE key='owned_products_retrieved'")
+        .fetch_one(&mut *connection)
+        .await;
+
+    match res {
+        Ok(result) => {
+            let value = result
+                .try_get("value")
+                .unwrap_or("0")
+                .parse::<u8>()
+                .unwrap();
+            !result.is_empty() && value != 0
+        }
+        Err(_) => false,
+    }
+}
+
+pub async fn set_owned_products(database: SqlitePool, products: &[u64]) -> Result<(), Error> {
+    let mut connection = database.acquire().await?;
+    let mut transaction = connection.begin().await?;
+
+    sqlx::query("DELETE FROM owned_product")
+        .execute(&mut *transaction)
+        .await?;
+    for product_id in products {
+        sqlx::query("INSERT INTO owned_product VALUES ($1) ON CONFLICT(id) DO NOTHING")
+            .bind(*product_id as i64)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    sqlx::query(
+        "INSERT INTO database_info VALUES ('owned_products_retrieved', '1') ON CONFLICT(key) DO UPDATE SET value=excluded.value",
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn is_product_owned(database: &SqlitePool, product_id: u64) -> Result<bool, Error> {
+    let mut connection = database.acquire().await?;
+    let res = sqlx::query("SELECT id FROM owned_product WHERE id=$1")
+        .bind(product_id as i64)
+        .fetch_optional(&mut *connection)
+        .await?;
+    Ok(res.is_some())
+}
+
 pub async fn update_leaderboards(
     context: &HandlerContext,
     leaderboard_definitions: &Vec<LeaderboardDefinition>,
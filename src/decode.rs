@@ -0,0 +1,169 @@
+use crate::proto::common_utils::ProtoPayload;
+use crate::proto::*;
+use protobuf::MessageDyn;
+use protobuf::reflect::{FileDescriptor, MessageDescriptor};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Modules that define `MessageSort` and `MessageType` enums
+fn modules() -> [FileDescriptor; 8] {
+    [
+        galaxy_protocols_communication_service::file_descriptor().clone(),
+        galaxy_protocols_webbroker_service::file_descriptor().clone(),
+        galaxy_protocols_overlay_for_service::file_descriptor().clone(),
+        galaxy_protocols_overlay_for_peer::file_descriptor().clone(),
+        galaxy_protocols_overlay_for_client::file_descriptor().clone(),
+        galaxy_common_protocols_peer_to_server::file_descriptor().clone(),
+        galaxy_common_protocols_peer_to_peer::file_descriptor().clone(),
+        galaxy_common_protocols_connection::file_descriptor().clone(),
+    ]
+}
+
+fn module_sort(module: &FileDescriptor) -> Option<i32> {
+    module
+        .enum_by_package_relative_name("MessageSort")?
+        .value_by_name("MESSAGE_SORT")
+        .map(|value| value.value())
+}
+
+// AUTH_INFO_REQUEST -> AuthInfoRequest
+fn message_name(type_name: &str) -> String {
+    type_name
+        .split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+pub struct MessageInfo {
+    /// Name of the proto module e.g. galaxy.protocols.communication_service
+    pub module: String,
+    /// Name of the MessageType enum value
+    pub type_name: String,
+    pub descriptor: Option<MessageDescriptor>,
+}
+
+/// Resolve sort and type to the generated MessageType enums.
+/// Connection messages (ping/pong) are sent along with other sorts, so they are used as a fallback
+pub fn resolve(sort: u32, type_: u32) -> Option<MessageInfo> {
+    let sort: i32 = sort.try_into().ok()?;
+    let type_: i32 = type_.try_into().ok()?;
+    let modules = modules();
+    let (exact, fallback): (Vec<&FileDescriptor>, Vec<&FileDescriptor>) = modules
+        .iter()
+        .partition(|module| module_sort(module) == Some(sort));
+
+    exact.into_iter().chain(fallback).find_map(|module| {
+        let type_name = module
+            .enum_by_package_relative_name("MessageType")?
+            .value_by_number(type_)?
+            .name()
+            .to_owned();
+        Some(MessageInfo {
+            module: module.package().to_owned(),
+            descriptor: module.message_by_package_relative_name(&message_name(&type_name)),
+            type_name,
+        })
+    })
+}
+
+/// Split raw bytes into frames, trailing incomplete data is ignored
+pub fn split_frames(mut data: &[u8]) -> Vec<ProtoPayload> {
+    let mut frames = Vec::new();
+    while let Some(frame) = ProtoPayload::from_frame(data) {
+        let header_size: usize = u16::from_be_bytes([data[0], data[1]]).into();
+        data = &data[2 + header_size + frame.payload.len()..];
+        frames.push(frame);
+    }
+    frames
+}
+
+/// Parse hex dump, whitespace, `:` separators and `0x` prefixes are ignored
+pub fn parse_hex(input: &str) -> Result<Vec<u8>, String> {
+    let digits: String = input
+        .split_whitespace()
+        .flat_map(|chunk| chunk.split(':'))
+        .map(|chunk| chunk.trim_start_matches("0x"))
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(String::from("odd number of hex digits"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("invalid hex byte {:?}", &digits[i..i + 2]))
+        })
+        .collect()
+}
+
+fn print_message(message: &dyn MessageDyn, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => protobuf::text_format::print_to_string_pretty(message),
+        OutputFormat::Json => protobuf_json_mapping::print_to_string(message)
+            .unwrap_or_else(|err| format!("<failed to print json {err}>")),
+    }
+}
+
+/// One line description of the frame, e.g.
+/// `1:5 galaxy.protocols.communication_service.AUTH_INFO_REQUEST (30 bytes)`
+pub fn describe(payload: &ProtoPayload) -> String {
+    let sort = payload.header.sort();
+    let type_ = payload.header.type_();
+    match resolve(sort, type_) {
+        Some(info) => format!(
+            "{sort}:{type_} {}.{} ({} bytes)",
+            info.module,
+            info.type_name,
+            payload.payload.len()
+        ),
+        None => format!("{sort}:{type_} unknown ({} bytes)", payload.payload.len()),
+    }
+}
+
+/// Decoded header and payload
+pub fn decode(payload: &ProtoPayload, format: OutputFormat) -> String {
+    let header = print_message(&payload.header, format);
+    let body = match resolve(payload.header.sort(), payload.header.type_()) {
+        Some(MessageInfo {
+            descriptor: Some(descriptor),
+            ..
+        }) => match descriptor.parse_from_bytes(&payload.payload) {
+            Ok(message) => print_message(&*message, format),
+            Err(err) => format!("<failed to parse {}: {err}>", descriptor.name()),
+        },
+        _ if payload.payload.is_empty() => String::new(),
+        _ => format!("<no message definition> {:02x?}", payload.payload),
+    };
+
+    match format {
+        OutputFormat::Text => format!(
+            "{}\nheader {{\n{}}}\npayload {{\n{}}}",
+            describe(payload),
+            header,
+            body
+        ),
+        OutputFormat::Json => format!(
+            "{{\"description\":{},\"header\":{},\"payload\":{}}}",
+            serde_json::Value::from(describe(payload)),
+            header,
+            if body.starts_with('{') {
+                body
+            } else {
+                serde_json::Value::from(body).to_string()
+            }
+        ),
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use log::{debug, warn};
+use protobuf::Message;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::api::gog::friends::{self, User};
+use crate::api::notification_pusher::NotificationPusherClient;
+use crate::constants::TokenStorage;
+use crate::paths;
+use crate::proto::galaxy_protocols_webbroker_service::MessageFromTopic;
+
+/// Friends and users seen by the user, kept so names and avatars are available offline
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct FriendsCache {
+    friends: Vec<String>,
+    users: HashMap<String, User>,
+    /// When details of the user were fetched, unix timestamp
+    #[serde(default)]
+    fetched: HashMap<String, i64>,
+}
+
+impl FriendsCache {
+    fn insert_user(&mut self, user: User, time: i64) {
+        self.fetched.insert(user.id.clone(), time);
+        self.users.insert(user.id.clone(), user);
+    }
+
+    fn is_fresh(&self, user_id: &str, now: i64) -> bool {
+        let ttl: i64 = crate::CONFIG
+            .get()
+            .runtime
+            .friends_cache_ttl
+            .try_into()
+            .unwrap_or(i64::MAX);
+        self.users.contains_key(user_id)
+            && self
+                .fetched
+                .get(user_id)
+                .is_some_and(|fetched| fetched.saturating_add(ttl) > now)
+    }
+}
+
+lazy_static! {
+    // Connections of different games update the same cache file
+    static ref CACHE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+fn cache_path(user_id: &str) -> PathBuf {
+    paths::FRIENDS_CACHE.join(format!("{user_id}.json"))
+}
+
+async fn load_cache(user_id: &str) -> FriendsCache {
+    match tokio::fs::read(cache_path(user_id)).await {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+            warn!("Invalid friends cache, starting over {err}");
+            FriendsCache::default()
+        }),
+        Err(_) => FriendsCache::default(),
+    }
+}
+
+async fn save_cache(user_id: &str, cache: &FriendsCache) {
+    let path = cache_path(user_id);
+    if let Some(parent) = path.parent() {
+        let _ = tokio::fs::create_dir_all(parent).await;
+    }
+    let data = serde_json::to_vec(cache).unwrap();
+    // Readers never see a partially written file
+    let temp_path = path.with_extension("json.tmp");
+    let result = match tokio::fs::write(&temp_path, data).await {
+        Ok(()) => tokio::fs::rename(&temp_path, &path).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        warn!("Failed to write friends cache {err}");
+    }
+}
+
+// Apply changes to the latest cache on disk, so updates from other connections are kept
+async fn update_cache(user_id: &str, update: impl FnOnce(&mut FriendsCache)) -> FriendsCache {
+    let _lock = CACHE_LOCK.lock().await;
+    let mut cache = load_cache(user_id).await;
+    update(&mut cache);
+    save_cache(user_id, &cache).await;
+    cache
+}
+
+/// Friends of the user, fetched when online and served from the cache otherwise
+pub async fn friend_list(
+    token_store: &TokenStorage,
+    user_id: &str,
+    reqwest_client: &Client,
+    online: bool,
+) -> Vec<User> {
+    let fetched = if online {
+        match friends::fetch_friends(token_store, user_id, reqwest_client).await {
+            Ok(friends) => Some(friends),
+            Err(err) => {
+                warn!("Failed to fetch friends, using cache {:?}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let cache = match fetched {
+        Some(friends) => {
+            let now = chrono::Utc::now().timestamp();
+            update_cache(user_id, |cache| {
+                cache.friends = friends.iter().map(|friend| friend.id.clone()).collect();
+                for friend in friends {
+                    cache.insert_user(friend, now);
+                }
+            })
+            .await
+        }
+        None => load_cache(user_id).await,
+    };
+
+    cache
+        .friends
+        .iter()
+        .filter_map(|id| cache.users.get(id).cloned())
+        .collect()
+}
+
+/// Details of the requested users, users not known yet or older than
+/// `runtime.friends_cache_ttl` are fetched when online
+pub async fn users(
+    token_store: &TokenStorage,
+    user_id: &str,
+    user_ids: &[String],
+    reqwest_client: &Client,
+    online: bool,
+) -> Vec<User> {
+    let mut cache = load_cache(user_id).await;
+    if online {
+        let now = chrono::Utc::now().timestamp();
+        let mut fetched = Vec::new();
+        for id in user_ids {
+            if cache.is_fresh(id, now) {
+                continue;
+            }
+            match friends::fetch_user(token_store, id, reqwest_client).await {
+                Ok(user) => fetched.push(user),
+                Err(err) => warn!("Failed to fetch user {id} {:?}", err),
+            }
+        }
+        if !fetched.is_empty() {
+            cache = update_cache(user_id, |cache| {
+                for user in fetched {
+                    cache.insert_user(user, now);
+                }
+            })
+            .await;
+        }
+    }
+
+    user_ids
+        .iter()
+        .filter_map(|id| cache.users.get(id).cloned())
+        .collect()
+}
+
+/// Cached details of the user when they are a friend
+pub async fn cached_friend(user_id: &str, friend_id: &str) -> Option<User> {
+    let mut cache = load_cache(user_id).await;
+    if !cache.friends.iter().any(|id| id == friend_id) {
+        return None;
+    }
+    cache.users.remove(friend_id)
+}
+
+lazy_static! {
+    static ref ONLINE_USERS: std::sync::Mutex<HashSet<String>> =
+        std::sync::Mutex::new(HashSet::new());
+}
+
+#[derive(Deserialize, Debug)]
+struct PresenceUpdate {
+    #[serde(deserialize_with = "deserialize_id")]
+    user_id: String,
+    presence: String,
+}
+
+// User ids come both as numbers and strings
+fn deserialize_id<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    match value {
+        serde_json::Value::String(id) => Ok(id),
+        serde_json::Value::Number(id) => Ok(id.to_string()),
+        _ => Err(serde::de::Error::custom("invalid user id")),
+    }
+}
+
+/// Whether the user was reported online by the presence topic
+pub fn is_user_online(user_id: &str) -> bool {
+    ONLINE_USERS.lock().unwrap().contains(user_id)
+}
+
+/// User id and whether they are online from a `presence` topic message
+pub fn parse_presence_message(message: &Vec<u8>) -> Option<(String, bool)> {
+    let payload = NotificationPusherClient::parse_message(message).ok()?;
+    let topic_message = MessageFromTopic::parse_from_bytes(&payload.payload).ok()?;
+    match serde_json::from_slice::<PresenceUpdate>(topic_message.content()) {
+        Ok(update) => Some((update.user_id, update.presence != "offline")),
+        Err(err) => {
+            warn!("Failed to parse presence update {err}");
+            None
+        }
+    }
+}
+
+/// Keep friends presence up to date from notifications pusher `presence` topic messages
+pub fn handle_presence_message(message: &Vec<u8>) {
+    let Some((user_id, is_online)) = parse_presence_message(message) else {
+        return;
+    };
+    debug!("Presence of {user_id} is online: {is_online}");
+    let mut online = ONLINE_USERS.lock().unwrap();
+    if is_online {
+        online.insert(user_id);
+    } else {
+        online.remove(&user_id);
+    }
+}
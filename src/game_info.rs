@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::config::GameSettings;
+use crate::paths;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GameInfo {
+    game_id: String,
+    root_game_id: Option<String>,
+    client_id: Option<String>,
+}
+
+lazy_static! {
+    // Connections remembering products at the same time would drop each other's entries
+    static ref KNOWN_PRODUCTS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// Product id from `goggame-*.info` file in the directory belonging to client_id
+async fn read_game_info(dir: &Path, client_id: &str) -> Option<String> {
+    let mut entries = tokio::fs::read_dir(dir).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with("goggame-") || !name.ends_with(".info") {
+            continue;
+        }
+        let Ok(data) = tokio::fs::read(entry.path()).await else {
+            continue;
+        };
+        let info: GameInfo = match serde_json::from_slice(&data) {
+            Ok(info) => info,
+            Err(err) => {
+                debug!("Invalid game info {name} {err}");
+                continue;
+            }
+        };
+        if info.client_id.as_deref() == Some(client_id) {
+            // DLC info files share client_id with the base game
+            return Some(info.root_game_id.unwrap_or(info.game_id));
+        }
+    }
+    None
+}
+
+// Executable may live in a subdirectory like bin/x64, info files are in the install root
+#[cfg(target_os = "linux")]
+async fn process_dirs(pid: u32) -> Vec<PathBuf> {
+    let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+    let mut dirs = Vec::new();
+    if let Ok(cwd) = tokio::fs::read_link(proc_dir.join("cwd")).await {
+        dirs.push(cwd);
+    }
+    if let Ok(exe) = tokio::fs::read_link(proc_dir.join("exe")).await {
+        dirs.extend(exe.ancestors().skip(1).take(3).map(Path::to_path_buf));
+    }
+    dirs
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn process_dirs(_pid: u32) -> Vec<PathBuf> {
+    Vec::new()
+}
+
+async fn find_in_process(pid: u32, client_id: &str) -> Option<String> {
+    for dir in process_dirs(pid).await {
+        if let Some(product_id) = read_game_info(&dir, client_id).await {
+            return Some(product_id);
+        }
+    }
+    None
+}
+
+// Games running through Wine report Windows PIDs, look for any process started from the install
+#[cfg(target_os = "linux")]
+async fn find_in_processes(client_id: &str) -> Option<String> {
+    let mut entries = tokio::fs::read_dir("/proc").await.ok()?;
+    let own_pid = std::process::id();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse().ok()) else {
+            continue;
+        };
+        if pid == own_pid {
+            continue;
+        }
+        if let Some(product_id) = find_in_process(pid, client_id).await {
+            return Some(product_id);
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn find_in_processes(_client_id: &str) -> Option<String> {
+    None
+}
+
+async fn load_known_products() -> HashMap<String, String> {
+    match tokio::fs::read(paths::KNOWN_PRODUCTS.as_path()).await {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_default(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+async fn remember_product(client_id: &str, product_id: &str) {
+    let _lock = KNOWN_PRODUCTS_LOCK.lock().await;
+    let mut known = load_known_products().await;
+    if known.get(client_id).map(String::as_str) == Some(product_id) {
+        return;
+    }
+    known.insert(client_id.to_owned(), product_id.to_owned());
+    if let Some(parent) = paths::KNOWN_PRODUCTS.parent() {
+        let _ = tokio::fs::create_dir_all(parent).await;
+    }
+    let data = serde_json::to_vec(&known).unwrap();
+    // Readers never see a partially written file
+    let path = paths::KNOWN_PRODUCTS.as_path();
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    let result = match tokio::fs::write(&temp_path, data).await {
+        Ok(()) => tokio::fs::rename(&temp_path, path).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        warn!("Failed to save product id of {client_id} {err}");
+    }
+}
+
+/// Find GOG product id of the game.
+/// Checked in order: `product_id` game setting, `goggame-*.info` next to the game process,
+/// product ids found for client_id previously and `HEROIC_APP_NAME` environment variable.
+/// When none of them has it, look for the game with [`find_running_product_id`]
+pub async fn resolve_product_id(
+    client_id: &str,
+    pid: u32,
+    settings: &GameSettings,
+) -> Option<String> {
+    if let Some(product_id) = &settings.product_id {
+        return Some(product_id.clone());
+    }
+
+    if let Some(product_id) = find_in_process(pid, client_id).await {
+        info!("Detected product id {product_id} for {client_id}");
+        remember_product(client_id, &product_id).await;
+        return Some(product_id);
+    }
+
+    if let Some(product_id) = load_known_products().await.remove(client_id) {
+        debug!("Using previously detected product id {product_id} for {client_id}");
+        return Some(product_id);
+    }
+
+    std::env::var("HEROIC_APP_NAME")
+        .ok()
+        .filter(|product_id| !product_id.is_empty())
+}
+
+/// Product id from `goggame-*.info` next to any running process.
+/// Reads every process in /proc, so it runs in the background after the game is answered
+pub async fn find_running_product_id(client_id: &str) -> Option<String> {
+    let product_id = find_in_processes(client_id).await?;
+    info!("Detected product id {product_id} for {client_id}");
+    remember_product(client_id, &product_id).await;
+    Some(product_id)
+}
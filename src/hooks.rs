@@ -0,0 +1,180 @@
+use crate::api::gog::achievements::Achievement;
+use std::process::Stdio;
+use tokio::process::Command;
+
+pub enum HookEvent {
+    AchievementUnlocked {
+        client_id: String,
+        achievement: Achievement,
+    },
+    LeaderboardScorePosted {
+        client_id: String,
+        leaderboard_id: String,
+        score: i32,
+        old_rank: u32,
+        new_rank: u32,
+        entry_total_count: u32,
+    },
+    LeaderboardRankChanged {
+        client_id: String,
+        leaderboard_id: String,
+        score: i32,
+        old_rank: u32,
+        new_rank: u32,
+        entry_total_count: u32,
+    },
+    SessionStart {
+        client_id: String,
+        pid: u32,
+    },
+    SessionEnd {
+        client_id: String,
+        pid: u32,
+    },
+    SyncFailed {
+        client_id: String,
+        kind: &'static str,
+        error: String,
+    },
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::AchievementUnlocked { .. } => "achievement_unlocked",
+            Self::LeaderboardScorePosted { .. } => "leaderboard_score_posted",
+            Self::LeaderboardRankChanged { .. } => "leaderboard_rank_changed",
+            Self::SessionStart { .. } => "session_start",
+            Self::SessionEnd { .. } => "session_end",
+            Self::SyncFailed { .. } => "sync_failed",
+        }
+    }
+
+    fn command(&self) -> Option<String> {
+        let config = crate::CONFIG.get();
+        let hooks = &config.hooks;
+        let command = match self {
+            Self::AchievementUnlocked { .. } => &hooks.achievement_unlocked,
+            Self::LeaderboardScorePosted { .. } => &hooks.leaderboard_score_posted,
+            Self::LeaderboardRankChanged { .. } => &hooks.leaderboard_rank_changed,
+            Self::SessionStart { .. } => &hooks.session_start,
+            Self::SessionEnd { .. } => &hooks.session_end,
+            Self::SyncFailed { .. } => &hooks.sync_failed,
+        };
+        command.clone().filter(|command| !command.trim().is_empty())
+    }
+
+    fn environment(self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::AchievementUnlocked {
+                client_id,
+                achievement,
+            } => vec![
+                ("COMET_CLIENT_ID", client_id),
+                ("COMET_ACHIEVEMENT_ID", achievement.achievement_id),
+                ("COMET_ACHIEVEMENT_KEY", achievement.achievement_key),
+                ("COMET_ACHIEVEMENT_NAME", achievement.name),
+                ("COMET_ACHIEVEMENT_DESCRIPTION", achievement.description),
+                (
+                    "COMET_ACHIEVEMENT_IMAGE_URL",
+                    achievement.image_url_unlocked,
+                ),
+                (
+                    "COMET_ACHIEVEMENT_UNLOCK_TIME",
+                    achievement.date_unlocked.unwrap_or_default(),
+                ),
+            ],
+            Self::LeaderboardScorePosted {
+                client_id,
+                leaderboard_id,
+                score,
+                old_rank,
+                new_rank,
+                entry_total_count,
+            }
+            | Self::LeaderboardRankChanged {
+                client_id,
+                leaderboard_id,
+                score,
+                old_rank,
+                new_rank,
+                entry_total_count,
+            } => vec![
+                ("COMET_CLIENT_ID", client_id),
+                ("COMET_LEADERBOARD_ID", leaderboard_id),
+                ("COMET_LEADERBOARD_SCORE", score.to_string()),
+                ("COMET_LEADERBOARD_OLD_RANK", old_rank.to_string()),
+                ("COMET_LEADERBOARD_NEW_RANK", new_rank.to_string()),
+                (
+                    "COMET_LEADERBOARD_ENTRY_TOTAL_COUNT",
+                    entry_total_count.to_string(),
+                ),
+            ],
+            Self::SessionStart { client_id, pid } | Self::SessionEnd { client_id, pid } => vec![
+                ("COMET_CLIENT_ID", client_id),
+                ("COMET_GAME_PID", pid.to_string()),
+            ],
+            Self::SyncFailed {
+                client_id,
+                kind,
+                error,
+            } => vec![
+                ("COMET_CLIENT_ID", client_id),
+                ("COMET_SYNC_KIND", kind.to_string()),
+                ("COMET_SYNC_ERROR", error),
+            ],
+        }
+    }
+}
+
+/// Run the command configured for the event in the `[hooks]` section, if any.
+/// The command is spawned in the background, event data is passed through `COMET_*` variables
+pub fn trigger(event: HookEvent) {
+    let Some(command) = event.command() else {
+        return;
+    };
+    let event_name = event.name();
+
+    #[cfg(unix)]
+    let mut process = {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(&command);
+        process
+    };
+    #[cfg(windows)]
+    let mut process = {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(&command);
+        process
+    };
+
+    process
+        .env("COMET_EVENT", event_name)
+        .envs(event.environment())
+        .stdin(Stdio::null());
+    // Socket passed to comet by the service manager isn't theirs
+    #[cfg(unix)]
+    process
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDS")
+        .env_remove("LISTEN_FDNAMES");
+
+    log::debug!("Running {event_name} hook: {command}");
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            log::error!("Failed to run {event_name} hook {err}");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if !status.success() => {
+                log::warn!("The {event_name} hook exited with {status}")
+            }
+            Ok(_) => (),
+            Err(err) => log::error!("Failed to wait for {event_name} hook {err}"),
+        }
+    });
+}
@@ -2,17 +2,30 @@
 extern crate lazy_static;
 
 pub mod api;
+pub mod capture;
+pub mod cloud;
 pub mod config;
 pub mod constants;
 pub mod db;
+pub mod decode;
+pub mod friends;
+pub mod game_info;
+pub mod hooks;
+pub mod listener;
+pub mod lobby;
+#[cfg(target_os = "linux")]
+pub mod notifications;
+pub mod overlay;
 pub mod paths;
+pub mod products;
 pub mod proto;
+pub mod reload;
 pub mod workarounds;
 
 pub static CERT: &[u8] = include_bytes!("../external/rootCA.pem");
 
 lazy_static! {
-    pub static ref CONFIG: config::Configuration = config::load_config().unwrap_or_default();
+    pub static ref CONFIG: config::SharedConfiguration = config::SharedConfiguration::load();
     pub static ref LOCALE: String = sys_locale::get_locale()
         .and_then(|x| if !x.contains("-") { None } else { Some(x) })
         .unwrap_or_else(|| String::from("en-US"));
@@ -0,0 +1,99 @@
+use log::{error, info};
+use tokio::net::TcpListener;
+
+// First file descriptor passed by the service manager
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Take over a socket passed with the systemd socket activation protocol
+/// (`LISTEN_PID` and `LISTEN_FDS` variables). Only the first socket is used
+#[cfg(unix)]
+fn activated_listener() -> Result<Option<TcpListener>, std::io::Error> {
+    use std::os::fd::FromRawFd;
+
+    let listen_pid = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    if listen_pid != Some(std::process::id()) {
+        return Ok(None);
+    }
+    let listen_fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<i32>().ok())
+        .unwrap_or(0);
+    if listen_fds < 1 {
+        return Ok(None);
+    }
+    if listen_fds > 1 {
+        log::warn!("Received {listen_fds} sockets, only the first one will be used");
+    }
+
+    // SAFETY: the service manager hands the descriptor over to this process
+    let socket = unsafe { socket2::Socket::from_raw_fd(SD_LISTEN_FDS_START) };
+    // Hook commands must not inherit the socket, activation variables are removed from their
+    // environment in hooks
+    socket.set_cloexec(true)?;
+    if socket.r#type()? != socket2::Type::STREAM {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Socket passed by the service manager is not a stream socket",
+        ));
+    }
+    let listener = std::net::TcpListener::from(socket);
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    info!(
+        "Using socket from the service manager {:?}",
+        listener.local_addr()
+    );
+    Ok(Some(listener))
+}
+
+fn configured_address() -> String {
+    let config = crate::CONFIG.get();
+    format!("{}:{}", config.listen.address, config.listen.port)
+}
+
+/// Get the socket games connect to, either the one passed by the service manager
+/// or a new one bound to the address from the `[listen]` configuration (or `address` override).
+/// Address is returned when it came from the configuration, so it can be followed with [`rebind`]
+pub async fn bind(address: Option<&str>) -> Result<(TcpListener, Option<String>), std::io::Error> {
+    #[cfg(unix)]
+    if let Some(listener) = activated_listener()? {
+        return Ok((listener, None));
+    }
+
+    let (address, configured) = match address {
+        Some(address) => (address.to_owned(), None),
+        None => {
+            let address = configured_address();
+            (address.clone(), Some(address))
+        }
+    };
+    let listener = TcpListener::bind(&address).await?;
+    // Port 0 is replaced with the one picked by the system
+    match listener.local_addr() {
+        Ok(local_address) => info!("Listening on {local_address}"),
+        Err(_) => info!("Listening on {address}"),
+    }
+    Ok((listener, configured))
+}
+
+/// Bind the address from `[listen]` when it differs from `current`, used after configuration
+/// reload. Connected games stay connected, new ones have to use the new address
+pub async fn rebind(current: &str) -> Option<(TcpListener, String)> {
+    let address = configured_address();
+    if address == current {
+        return None;
+    }
+    match TcpListener::bind(&address).await {
+        Ok(listener) => {
+            info!("Listening on {address}");
+            Some((listener, address))
+        }
+        Err(err) => {
+            error!("Failed to listen on {address}, keeping {current} {err}");
+            None
+        }
+    }
+}
@@ -0,0 +1,791 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use base64::prelude::*;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+// Bumped on incompatible changes to LAN messages
+const LAN_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Member {
+    pub user_id: u64,
+    pub data: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Lobby {
+    pub id: u64,
+    pub client_id: String,
+    pub owner: u64,
+    pub max_members: u32,
+    pub joinable: bool,
+    /// Shown in lobby lists, private lobbies can only be joined by id
+    pub listed: bool,
+    pub data: HashMap<String, String>,
+    pub members: Vec<Member>,
+}
+
+impl Lobby {
+    pub fn member(&self, user_id: u64) -> Option<&Member> {
+        self.members.iter().find(|member| member.user_id == user_id)
+    }
+
+    fn is_full(&self) -> bool {
+        self.max_members != 0 && self.members.len() >= self.max_members as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemberState {
+    Entered,
+    Left,
+}
+
+/// Changes delivered to games of the lobby's client_id
+#[derive(Debug, Clone)]
+pub enum LobbyEvent {
+    MemberState {
+        lobby_id: u64,
+        member_id: u64,
+        state: MemberState,
+    },
+    /// Lobby data when member_id is None, otherwise data of the member
+    DataUpdated {
+        lobby_id: u64,
+        member_id: Option<u64>,
+    },
+    Packet {
+        sender_id: u64,
+        channel: u32,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LobbyError {
+    DoesNotExist,
+    IsFull,
+    NotAllowed,
+    Timeout,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LanFrame {
+    comet_lan: u32,
+    instance: u64,
+    user_id: u64,
+    message: LanMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LanMessage {
+    /// Every lobby hosted by the sender
+    Announce {
+        lobbies: Vec<Lobby>,
+    },
+    Closed {
+        lobby_id: u64,
+    },
+    Join {
+        lobby_id: u64,
+    },
+    Leave {
+        lobby_id: u64,
+    },
+    SetMemberData {
+        lobby_id: u64,
+        key: String,
+        value: String,
+    },
+    Packet {
+        recipient_id: u64,
+        client_id: String,
+        channel: u32,
+        data: String,
+    },
+}
+
+struct LobbyEntry {
+    lobby: Lobby,
+    /// Instance hosting the lobby, None when hosted by us
+    host: Option<SocketAddr>,
+    seen: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    user_id: u64,
+    lobbies: HashMap<u64, LobbyEntry>,
+    /// Addresses of other instances by their user id
+    peers: HashMap<u64, SocketAddr>,
+}
+
+struct LobbyService {
+    instance: u64,
+    state: Mutex<State>,
+    // Connections of games receiving lobby events keyed by their client_id
+    subscribers: Mutex<Vec<(String, UnboundedSender<LobbyEvent>)>>,
+    socket: OnceLock<Arc<UdpSocket>>,
+}
+
+lazy_static! {
+    static ref SERVICE: LobbyService = LobbyService {
+        instance: rand::random(),
+        state: Mutex::new(State::default()),
+        subscribers: Mutex::new(Vec::new()),
+        socket: OnceLock::new(),
+    };
+}
+
+/// Deliver lobby events of client_id to sender until its receiver is dropped
+pub fn subscribe(client_id: &str, sender: &UnboundedSender<LobbyEvent>) {
+    let mut subscribers = SERVICE.subscribers.lock().unwrap();
+    // Connection identifying again replaces its previous subscription
+    subscribers.retain(|(_, subscriber)| !subscriber.same_channel(sender));
+    subscribers.push((client_id.to_owned(), sender.clone()));
+}
+
+fn emit(client_id: &str, events: Vec<LobbyEvent>) {
+    let mut subscribers = SERVICE.subscribers.lock().unwrap();
+    subscribers.retain(|(_, subscriber)| !subscriber.is_closed());
+    for (_, subscriber) in subscribers.iter().filter(|(id, _)| id == client_id) {
+        for event in &events {
+            let _ = subscriber.send(event.clone());
+        }
+    }
+}
+
+// Events for our user describing the change of a lobby it is a member of
+fn diff(old: Option<&Lobby>, new: Option<&Lobby>, user_id: u64) -> Vec<LobbyEvent> {
+    let mut events = Vec::new();
+    let was_member = old.is_some_and(|lobby| lobby.member(user_id).is_some());
+    let is_member = new.is_some_and(|lobby| lobby.member(user_id).is_some());
+    match (old, new) {
+        (Some(old), None) if was_member => {
+            // Lobby is gone, everyone else left
+            for member in old.members.iter().filter(|m| m.user_id != user_id) {
+                events.push(LobbyEvent::MemberState {
+                    lobby_id: old.id,
+                    member_id: member.user_id,
+                    state: MemberState::Left,
+                });
+            }
+        }
+        (Some(old), Some(new)) if was_member && is_member => {
+            for member in old.members.iter() {
+                if member.user_id != user_id && new.member(member.user_id).is_none() {
+                    events.push(LobbyEvent::MemberState {
+                        lobby_id: new.id,
+                        member_id: member.user_id,
+                        state: MemberState::Left,
+                    });
+                }
+            }
+            for member in new.members.iter() {
+                match old.member(member.user_id) {
+                    None if member.user_id != user_id => events.push(LobbyEvent::MemberState {
+                        lobby_id: new.id,
+                        member_id: member.user_id,
+                        state: MemberState::Entered,
+                    }),
+                    Some(previous) if previous.data != member.data => {
+                        events.push(LobbyEvent::DataUpdated {
+                            lobby_id: new.id,
+                            member_id: Some(member.user_id),
+                        })
+                    }
+                    _ => (),
+                }
+            }
+            if old.data != new.data {
+                events.push(LobbyEvent::DataUpdated {
+                    lobby_id: new.id,
+                    member_id: None,
+                });
+            }
+        }
+        _ => (),
+    }
+    events
+}
+
+// Apply a change to a lobby, notify local games and announce it when we host it
+async fn update<F>(lobby_id: u64, change: F) -> Result<Lobby, LobbyError>
+where
+    F: FnOnce(&mut Lobby) -> Result<(), LobbyError>,
+{
+    let (lobby, hosted) = {
+        let mut state = SERVICE.state.lock().unwrap();
+        let user_id = state.user_id;
+        let entry = state
+            .lobbies
+            .get_mut(&lobby_id)
+            .ok_or(LobbyError::DoesNotExist)?;
+        let old = entry.lobby.clone();
+        change(&mut entry.lobby)?;
+        emit(
+            &entry.lobby.client_id,
+            diff(Some(&old), Some(&entry.lobby), user_id),
+        );
+        (entry.lobby.clone(), entry.host.is_none())
+    };
+    if hosted {
+        announce().await;
+    }
+    Ok(lobby)
+}
+
+fn remove(lobby_id: u64) -> Option<Lobby> {
+    let mut state = SERVICE.state.lock().unwrap();
+    let user_id = state.user_id;
+    let entry = state.lobbies.remove(&lobby_id)?;
+    emit(
+        &entry.lobby.client_id,
+        diff(Some(&entry.lobby), None, user_id),
+    );
+    Some(entry.lobby)
+}
+
+fn host_of(lobby_id: u64) -> Result<Option<SocketAddr>, LobbyError> {
+    let state = SERVICE.state.lock().unwrap();
+    state
+        .lobbies
+        .get(&lobby_id)
+        .map(|entry| entry.host)
+        .ok_or(LobbyError::DoesNotExist)
+}
+
+pub async fn create(
+    client_id: &str,
+    user_id: u64,
+    max_members: u32,
+    joinable: bool,
+    listed: bool,
+) -> Lobby {
+    // Lobby ids have 56 bits, the rest is used by the id type flag
+    let lobby = Lobby {
+        id: rand::random::<u64>() >> 8,
+        client_id: client_id.to_owned(),
+        owner: user_id,
+        max_members,
+        joinable,
+        listed,
+        data: HashMap::new(),
+        members: vec![Member {
+            user_id,
+            data: HashMap::new(),
+        }],
+    };
+    info!("Created lobby {} for {}", lobby.id, client_id);
+    {
+        let mut state = SERVICE.state.lock().unwrap();
+        state.lobbies.insert(
+            lobby.id,
+            LobbyEntry {
+                lobby: lobby.clone(),
+                host: None,
+                seen: Instant::now(),
+            },
+        );
+    }
+    announce().await;
+    lobby
+}
+
+/// Joinable lobbies of the game, hosted by us or discovered in the network
+pub fn list(client_id: &str) -> Vec<Lobby> {
+    let state = SERVICE.state.lock().unwrap();
+    state
+        .lobbies
+        .values()
+        .map(|entry| &entry.lobby)
+        .filter(|lobby| lobby.client_id == client_id && lobby.listed && lobby.joinable)
+        .cloned()
+        .collect()
+}
+
+pub fn get(lobby_id: u64) -> Option<Lobby> {
+    let state = SERVICE.state.lock().unwrap();
+    state
+        .lobbies
+        .get(&lobby_id)
+        .map(|entry| entry.lobby.clone())
+}
+
+fn check_joinable(lobby: &Lobby, user_id: u64) -> Result<(), LobbyError> {
+    if lobby.member(user_id).is_some() {
+        return Ok(());
+    }
+    if !lobby.joinable {
+        return Err(LobbyError::NotAllowed);
+    }
+    if lobby.is_full() {
+        return Err(LobbyError::IsFull);
+    }
+    Ok(())
+}
+
+fn add_member(lobby: &mut Lobby, user_id: u64) -> Result<(), LobbyError> {
+    check_joinable(lobby, user_id)?;
+    if lobby.member(user_id).is_none() {
+        lobby.members.push(Member {
+            user_id,
+            data: HashMap::new(),
+        });
+    }
+    Ok(())
+}
+
+pub async fn join(client_id: &str, lobby_id: u64, user_id: u64) -> Result<Lobby, LobbyError> {
+    let lobby = get(lobby_id).ok_or(LobbyError::DoesNotExist)?;
+    if lobby.client_id != client_id {
+        return Err(LobbyError::DoesNotExist);
+    }
+    check_joinable(&lobby, user_id)?;
+
+    let Some(host) = host_of(lobby_id)? else {
+        return update(lobby_id, |lobby| add_member(lobby, user_id)).await;
+    };
+
+    // The host confirms the join with an announcement listing us as a member
+    send(host, LanMessage::Join { lobby_id }).await;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        match get(lobby_id) {
+            Some(lobby) if lobby.member(user_id).is_some() => {
+                info!("Joined lobby {}", lobby_id);
+                return Ok(lobby);
+            }
+            Some(lobby) if lobby.is_full() => return Err(LobbyError::IsFull),
+            Some(_) => (),
+            None => return Err(LobbyError::DoesNotExist),
+        }
+    }
+    Err(LobbyError::Timeout)
+}
+
+pub async fn leave(lobby_id: u64, user_id: u64) {
+    let Ok(host) = host_of(lobby_id) else {
+        return;
+    };
+    info!("Leaving lobby {}", lobby_id);
+    match host {
+        None => {
+            let is_owner = get(lobby_id).is_some_and(|lobby| lobby.owner == user_id);
+            if is_owner {
+                // Lobby can't live without its host
+                let members = {
+                    let mut state = SERVICE.state.lock().unwrap();
+                    let Some(entry) = state.lobbies.remove(&lobby_id) else {
+                        return;
+                    };
+                    entry
+                        .lobby
+                        .members
+                        .iter()
+                        .filter_map(|member| state.peers.get(&member.user_id).copied())
+                        .collect::<Vec<_>>()
+                };
+                for address in members {
+                    send(address, LanMessage::Closed { lobby_id }).await;
+                }
+                broadcast(LanMessage::Closed { lobby_id }).await;
+            } else {
+                let _ = update(lobby_id, |lobby| {
+                    lobby.members.retain(|member| member.user_id != user_id);
+                    Ok(())
+                })
+                .await;
+            }
+        }
+        Some(host) => {
+            send(host, LanMessage::Leave { lobby_id }).await;
+            // Forget the lobby until the host announces it again
+            let mut state = SERVICE.state.lock().unwrap();
+            state.lobbies.remove(&lobby_id);
+        }
+    }
+}
+
+/// Leave every lobby the game is in, used when the game disconnects
+pub async fn leave_all(client_id: &str, user_id: u64) {
+    let lobbies: Vec<u64> = {
+        let state = SERVICE.state.lock().unwrap();
+        state
+            .lobbies
+            .values()
+            .filter(|entry| {
+                entry.lobby.client_id == client_id && entry.lobby.member(user_id).is_some()
+            })
+            .map(|entry| entry.lobby.id)
+            .collect()
+    };
+    for lobby_id in lobbies {
+        leave(lobby_id, user_id).await;
+    }
+}
+
+fn set_value(data: &mut HashMap<String, String>, key: String, value: String) {
+    // Empty value removes the key
+    if value.is_empty() {
+        data.remove(&key);
+    } else {
+        data.insert(key, value);
+    }
+}
+
+pub async fn set_data(
+    lobby_id: u64,
+    user_id: u64,
+    key: String,
+    value: String,
+) -> Result<(), LobbyError> {
+    if host_of(lobby_id)?.is_some() {
+        return Err(LobbyError::NotAllowed);
+    }
+    update(lobby_id, |lobby| {
+        if lobby.owner != user_id {
+            return Err(LobbyError::NotAllowed);
+        }
+        set_value(&mut lobby.data, key, value);
+        Ok(())
+    })
+    .await
+    .map(|_| ())
+}
+
+pub async fn set_member_data(
+    lobby_id: u64,
+    user_id: u64,
+    key: String,
+    value: String,
+) -> Result<(), LobbyError> {
+    let host = host_of(lobby_id)?;
+    if let Some(host) = host {
+        let message = LanMessage::SetMemberData {
+            lobby_id,
+            key: key.clone(),
+            value: value.clone(),
+        };
+        send(host, message).await;
+    }
+    update(lobby_id, |lobby| {
+        let member = lobby
+            .members
+            .iter_mut()
+            .find(|member| member.user_id == user_id)
+            .ok_or(LobbyError::NotAllowed)?;
+        set_value(&mut member.data, key, value);
+        Ok(())
+    })
+    .await
+    .map(|_| ())
+}
+
+pub async fn send_packet(
+    client_id: &str,
+    sender_id: u64,
+    recipient_id: u64,
+    channel: u32,
+    data: Vec<u8>,
+) {
+    let address = {
+        let state = SERVICE.state.lock().unwrap();
+        if recipient_id == state.user_id {
+            None
+        } else {
+            match state.peers.get(&recipient_id) {
+                Some(address) => Some(*address),
+                None => {
+                    warn!("No route to {}, dropping packet", recipient_id);
+                    return;
+                }
+            }
+        }
+    };
+    match address {
+        None => emit(
+            client_id,
+            vec![LobbyEvent::Packet {
+                sender_id,
+                channel,
+                data,
+            }],
+        ),
+        Some(address) => {
+            let message = LanMessage::Packet {
+                recipient_id,
+                client_id: client_id.to_owned(),
+                channel,
+                data: BASE64_STANDARD.encode(data),
+            };
+            send(address, message).await
+        }
+    }
+}
+
+fn encode(message: LanMessage) -> Vec<u8> {
+    let user_id = SERVICE.state.lock().unwrap().user_id;
+    let frame = LanFrame {
+        comet_lan: LAN_PROTOCOL_VERSION,
+        instance: SERVICE.instance,
+        user_id,
+        message,
+    };
+    serde_json::to_vec(&frame).unwrap()
+}
+
+async fn send(address: SocketAddr, message: LanMessage) {
+    let Some(socket) = SERVICE.socket.get() else {
+        return;
+    };
+    if let Err(err) = socket.send_to(&encode(message), address).await {
+        warn!("Failed to send lobby message to {address} {err}");
+    }
+}
+
+async fn broadcast(message: LanMessage) {
+    let lan = &crate::CONFIG.get().lan;
+    let address = format!("{}:{}", lan.broadcast_address, lan.port);
+    match address.parse::<SocketAddr>() {
+        Ok(address) => send(address, message).await,
+        Err(err) => warn!("Invalid LAN broadcast address {address} {err}"),
+    }
+}
+
+// Send hosted lobbies to the network and directly to their members
+async fn announce() {
+    if SERVICE.socket.get().is_none() {
+        return;
+    }
+    let (lobbies, members) = {
+        let state = SERVICE.state.lock().unwrap();
+        let lobbies: Vec<Lobby> = state
+            .lobbies
+            .values()
+            .filter(|entry| entry.host.is_none())
+            .map(|entry| entry.lobby.clone())
+            .collect();
+        let mut members: Vec<SocketAddr> = lobbies
+            .iter()
+            .flat_map(|lobby| lobby.members.iter())
+            .filter_map(|member| state.peers.get(&member.user_id).copied())
+            .collect();
+        members.sort();
+        members.dedup();
+        (lobbies, members)
+    };
+    if lobbies.is_empty() {
+        return;
+    }
+    for address in members {
+        send(
+            address,
+            LanMessage::Announce {
+                lobbies: lobbies.clone(),
+            },
+        )
+        .await;
+    }
+    broadcast(LanMessage::Announce { lobbies }).await;
+}
+
+async fn handle_frame(data: &[u8], address: SocketAddr) {
+    let frame: LanFrame = match serde_json::from_slice(data) {
+        Ok(frame) => frame,
+        Err(err) => {
+            debug!("Ignoring invalid LAN message from {address} {err}");
+            return;
+        }
+    };
+    if frame.instance == SERVICE.instance {
+        return;
+    }
+    if frame.comet_lan != LAN_PROTOCOL_VERSION {
+        debug!("Ignoring LAN message with protocol {}", frame.comet_lan);
+        return;
+    }
+    let sender = frame.user_id;
+    SERVICE.state.lock().unwrap().peers.insert(sender, address);
+
+    match frame.message {
+        LanMessage::Announce { lobbies } => {
+            let mut state = SERVICE.state.lock().unwrap();
+            let user_id = state.user_id;
+            // Lobbies no longer hosted by the sender
+            let closed: Vec<u64> = state
+                .lobbies
+                .values()
+                .filter(|entry| {
+                    entry.host == Some(address)
+                        && !lobbies.iter().any(|lobby| lobby.id == entry.lobby.id)
+                })
+                .map(|entry| entry.lobby.id)
+                .collect();
+            for lobby_id in closed {
+                if let Some(entry) = state.lobbies.remove(&lobby_id) {
+                    emit(
+                        &entry.lobby.client_id,
+                        diff(Some(&entry.lobby), None, user_id),
+                    );
+                }
+            }
+            for lobby in lobbies {
+                let old = match state.lobbies.get(&lobby.id) {
+                    // Someone announces our lobby, ignore it
+                    Some(entry) if entry.host.is_none() => continue,
+                    Some(entry) => Some(entry.lobby.clone()),
+                    None => None,
+                };
+                emit(&lobby.client_id, diff(old.as_ref(), Some(&lobby), user_id));
+                state.lobbies.insert(
+                    lobby.id,
+                    LobbyEntry {
+                        lobby,
+                        host: Some(address),
+                        seen: Instant::now(),
+                    },
+                );
+            }
+        }
+        LanMessage::Closed { lobby_id } => {
+            if host_of(lobby_id) == Ok(Some(address)) {
+                remove(lobby_id);
+            }
+        }
+        LanMessage::Join { lobby_id } => {
+            if host_of(lobby_id) == Ok(None) {
+                match update(lobby_id, |lobby| add_member(lobby, sender)).await {
+                    Ok(_) => info!("{} joined lobby {}", sender, lobby_id),
+                    Err(err) => debug!("{} can't join lobby {} {:?}", sender, lobby_id, err),
+                }
+            }
+        }
+        LanMessage::Leave { lobby_id } => {
+            if host_of(lobby_id) == Ok(None) {
+                let _ = update(lobby_id, |lobby| {
+                    lobby.members.retain(|member| member.user_id != sender);
+                    Ok(())
+                })
+                .await;
+            }
+        }
+        LanMessage::SetMemberData {
+            lobby_id,
+            key,
+            value,
+        } => {
+            if host_of(lobby_id) == Ok(None) {
+                let _ = update(lobby_id, |lobby| {
+                    let member = lobby
+                        .members
+                        .iter_mut()
+                        .find(|member| member.user_id == sender)
+                        .ok_or(LobbyError::NotAllowed)?;
+                    set_value(&mut member.data, key, value);
+                    Ok(())
+                })
+                .await;
+            }
+        }
+        LanMessage::Packet {
+            recipient_id,
+            client_id,
+            channel,
+            data,
+        } => {
+            if recipient_id != SERVICE.state.lock().unwrap().user_id {
+                return;
+            }
+            match BASE64_STANDARD.decode(data) {
+                Ok(data) => emit(
+                    &client_id,
+                    vec![LobbyEvent::Packet {
+                        sender_id: sender,
+                        channel,
+                        data,
+                    }],
+                ),
+                Err(err) => debug!("Invalid packet data from {sender} {err}"),
+            }
+        }
+    }
+}
+
+// Forget lobbies whose host stopped announcing them
+fn expire(interval: Duration) {
+    let expired: Vec<u64> = {
+        let state = SERVICE.state.lock().unwrap();
+        state
+            .lobbies
+            .values()
+            .filter(|entry| entry.host.is_some() && entry.seen.elapsed() > interval * 3)
+            .map(|entry| entry.lobby.id)
+            .collect()
+    };
+    for lobby_id in expired {
+        debug!("Lobby {} expired", lobby_id);
+        remove(lobby_id);
+    }
+}
+
+/// Run the lobby service, exchanging lobbies with other instances in the local network
+pub async fn run(user_id: u64, shutdown_token: CancellationToken) {
+    SERVICE.state.lock().unwrap().user_id = user_id;
+
+    let (port, interval) = {
+        let config = crate::CONFIG.get();
+        if !config.lan.enabled {
+            info!("LAN lobby discovery is disabled");
+            return;
+        }
+        (
+            config.lan.port,
+            Duration::from_secs(config.lan.announce_interval.max(1)),
+        )
+    };
+    let socket = match UdpSocket::bind(("0.0.0.0", port)).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            warn!(
+                "Failed to bind LAN lobby socket on port {port}, only local lobbies will work {err}"
+            );
+            return;
+        }
+    };
+    if let Err(err) = socket.set_broadcast(true) {
+        warn!("Failed to enable broadcast on LAN lobby socket {err}");
+    }
+    let socket = Arc::new(socket);
+    let _ = SERVICE.socket.set(socket.clone());
+    info!("Discovering LAN lobbies on port {port}");
+
+    let mut buffer = vec![0; 65536];
+    // Ticks keep their schedule while datagrams keep arriving
+    let mut announce_timer = tokio::time::interval(interval);
+    announce_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buffer) => {
+                match received {
+                    Ok((size, address)) => handle_frame(&buffer[..size], address).await,
+                    Err(err) => debug!("Failed to receive LAN message {err}"),
+                }
+            }
+            _ = announce_timer.tick() => {
+                announce().await;
+                expire(interval);
+            }
+            _ = shutdown_token.cancelled() => break
+        }
+    }
+}
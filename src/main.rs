@@ -1,26 +1,92 @@
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 
-use clap::{Parser, Subcommand};
-use comet::api::gog::overlay::OverlayPeerMessage;
+use clap::{CommandFactory, Parser, Subcommand};
+use comet::api::gog::components::{Component, Platform};
+use comet::api::gog::presence::Presence;
 use env_logger::{Builder, Env, Target};
 use futures_util::future::join_all;
 use log::{error, info, warn};
 use reqwest::Client;
-use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 mod import_parsers;
+mod replay;
 
 use comet::api;
 use comet::constants;
 use comet::db;
+use comet::decode;
 use comet::paths;
 
 use comet::api::notification_pusher::NotificationPusherClient;
 use comet::api::notification_pusher::PusherEvent;
 use comet::api::structs::{Token, UserInfo};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::task::JoinHandle;
 
+#[derive(Subcommand, Debug)]
+enum CloudCommand {
+    #[command(about = "Synchronize local save directory with GOG cloud storage")]
+    Sync {
+        client_id: String,
+        #[arg(long)]
+        client_secret: String,
+        #[arg(long, help = "Local save directory")]
+        path: PathBuf,
+        #[arg(
+            long,
+            default_value = "__default",
+            help = "Name of the save location in the cloud"
+        )]
+        location: String,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "skip",
+            help = "What to do with files modified both locally and in the cloud"
+        )]
+        conflict: comet::cloud::ConflictResolution,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ComponentsCommand {
+    #[command(about = "Show installed components")]
+    List,
+    #[command(about = "Update components now, ignoring the check interval")]
+    Update {
+        #[arg(help = "Component to update, all installed when not set")]
+        component: Option<Component>,
+        #[arg(long, value_enum, default_value = "windows")]
+        platform: Platform,
+        #[arg(
+            long,
+            help = "Install from directory or zip archive with files-<platform>.json instead of GOG servers"
+        )]
+        from: Option<PathBuf>,
+    },
+    #[command(about = "Delete component files")]
+    Remove {
+        component: Component,
+        #[arg(long, value_enum, default_value = "windows")]
+        platform: Platform,
+    },
+    #[command(about = "Stop updating a component, optionally restoring the previous version")]
+    Pin {
+        component: Component,
+        #[arg(long, value_enum, default_value = "windows")]
+        platform: Platform,
+        #[arg(
+            long,
+            help = "Version to pin to, installed or the previous one kept after update"
+        )]
+        version: Option<String>,
+        #[arg(long, conflicts_with = "version", help = "Allow updates again")]
+        unpin: bool,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 enum SubCommand {
     #[command(about = "Preload achievements and statistics for offline usage")]
@@ -40,6 +106,40 @@ enum SubCommand {
         #[arg(long, help = "Force the download of non-native overlay")]
         force: bool,
     },
+
+    #[command(about = "Manage cloud saves")]
+    Cloud {
+        #[command(subcommand)]
+        command: CloudCommand,
+    },
+
+    #[command(about = "Manage downloaded Galaxy components")]
+    Components {
+        #[command(subcommand)]
+        command: ComponentsCommand,
+    },
+
+    #[command(
+        about = "Replay a capture recorded with --record against a new comet instance started with the same credentials"
+    )]
+    Replay {
+        capture: PathBuf,
+        #[arg(
+            long,
+            help = "Address of an already running comet to replay to, e.g. 127.0.0.1:9977"
+        )]
+        address: Option<String>,
+    },
+
+    #[command(about = "Decode frames from a capture file or a hex dump")]
+    Decode {
+        #[arg(help = "Capture file or file containing a hex dump")]
+        input: Option<PathBuf>,
+        #[arg(long, conflicts_with = "input", help = "Hex dump of the frames")]
+        hex: Option<String>,
+        #[arg(long, help = "Print payloads as JSON instead of protobuf text format")]
+        json: bool,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -52,7 +152,7 @@ struct Args {
     #[arg(long, help = "Galaxy user id from /userData.json")]
     user_id: Option<String>,
     #[arg(long, help = "User name")]
-    username: String,
+    username: Option<String>,
     #[arg(
         long = "from-heroic",
         help = "Load tokens from heroic",
@@ -82,14 +182,71 @@ struct Args {
         short,
         long,
         global = true,
-        help = "Make comet quit after every client disconnects. Use COMET_IDLE_WAIT environment variable to control the wait time (seconds)"
+        help = "Make comet quit after every client disconnects. Use runtime.idle_wait setting or COMET_IDLE_WAIT environment variable to control the wait time (seconds)"
     )]
     quit: bool,
 
+    #[arg(
+        long,
+        global = true,
+        help = "Record game, overlay and notifications pusher traffic to capture files in the directory"
+    )]
+    record: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Forward game connections to an upstream Galaxy communication service, e.g. 127.0.0.1:9978"
+    )]
+    proxy: Option<String>,
+    #[arg(
+        long,
+        requires = "proxy",
+        help = "Answer messages comet implements locally, forward only the rest"
+    )]
+    proxy_answer_locally: bool,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS:PORT",
+        help = "Address to listen on, overrides the [listen] configuration"
+    )]
+    listen: Option<String>,
+    // Used by replay to find out which port the instance it started got
+    #[arg(long, hide = true)]
+    print_address: bool,
+
     #[command(subcommand)]
     subcommand: Option<SubCommand>,
 }
 
+// Credential arguments for a comet instance started by replay
+fn instance_args(args: &Args) -> Vec<String> {
+    let mut instance_args = Vec::new();
+    for (name, value) in [
+        ("--access-token", &args.access_token),
+        ("--refresh-token", &args.refresh_token),
+        ("--user-id", &args.user_id),
+        ("--username", &args.username),
+    ] {
+        if let Some(value) = value {
+            instance_args.extend([name.to_owned(), value.clone()]);
+        }
+    }
+    if args.heroic {
+        instance_args.push(String::from("--from-heroic"));
+    }
+    #[cfg(target_os = "linux")]
+    if args.lutris {
+        instance_args.push(String::from("--from-lutris"));
+    }
+    #[cfg(target_os = "linux")]
+    if args.wyvern {
+        instance_args.push(String::from("--from-wyvern"));
+    }
+    instance_args
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
@@ -103,11 +260,11 @@ async fn main() {
         .filter_module("h2::codec", log::LevelFilter::Off)
         .init();
 
-    log::debug!("Configuration file {:?}", *comet::CONFIG);
-    log::info!("Preferred language: {}", comet::LOCALE.as_str());
-
-    let (access_token, refresh_token, galaxy_user_id) =
-        import_parsers::handle_credentials_import(&args);
+    log::debug!("Configuration file {:?}", comet::CONFIG.get());
+    log::info!(
+        "Preferred language: {}",
+        comet::CONFIG.get().resolve(None).language
+    );
 
     let certificate = reqwest::tls::Certificate::from_pem(comet::CERT).unwrap();
     let reqwest_client = Client::builder()
@@ -116,8 +273,42 @@ async fn main() {
         .build()
         .expect("Failed to build reqwest client");
 
+    // Commands that don't need any credentials
+    match &args.subcommand {
+        Some(SubCommand::Replay { capture, address }) => {
+            if !replay::replay(capture, address.as_deref(), &instance_args(&args)).await {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(SubCommand::Decode { input, hex, json }) => {
+            if !decode(input.as_deref(), hex.as_deref(), *json).await {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(SubCommand::Components { command }) => {
+            if !components(command, &reqwest_client).await {
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    let (access_token, refresh_token, galaxy_user_id) =
+        import_parsers::handle_credentials_import(&args);
+
+    let Some(username) = args.username.clone() else {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  --username <USERNAME>",
+            )
+            .exit();
+    };
     let user_info = Arc::new(UserInfo {
-        username: args.username,
+        username,
         galaxy_user_id: galaxy_user_id.clone(),
     });
 
@@ -132,8 +323,11 @@ async fn main() {
     tokio::spawn(async move {
         let mut retries = 0;
         loop {
-            if retries > 10 {
-                log::warn!("Failed to get peer libraries over 10 times, will not try again");
+            let max_retries = comet::CONFIG.get().runtime.component_retries;
+            if retries > max_retries {
+                log::warn!(
+                    "Failed to get peer libraries over {max_retries} times, will not try again"
+                );
                 return;
             }
             tokio::time::sleep(Duration::from_secs(retries * 5)).await;
@@ -179,10 +373,17 @@ async fn main() {
                 let database = db::gameplay::setup_connection(&client_id, &galaxy_user_id)
                     .await
                     .expect("Failed to setup the database");
+                let language = comet::CONFIG.get().resolve(Some(&client_id)).language;
+                // Achievements stored in a different language have to be fetched again
+                let language_changed = db::gameplay::get_achievements_language(&database)
+                    .await
+                    .is_some_and(|stored| stored != language);
 
                 if list
+                    || language_changed
                     || (!db::gameplay::has_achievements(&database).await
-                        || !db::gameplay::has_statistics(&database).await)
+                        || !db::gameplay::has_statistics(&database).await
+                        || !db::gameplay::has_owned_products(&database).await)
                 {
                     {
                         let mut connection = database.acquire().await.unwrap();
@@ -211,6 +412,7 @@ async fn main() {
                         &token_store,
                         &client_id,
                         &galaxy_user_id,
+                        &language,
                         &reqwest_client,
                     )
                     .await;
@@ -232,9 +434,14 @@ async fn main() {
                     }
 
                     if let Ok((achievements, mode)) = new_achievements {
-                        db::gameplay::set_achievements(database.clone(), &achievements, &mode)
-                            .await
-                            .expect("Failed to write to the database");
+                        db::gameplay::set_achievements(
+                            database.clone(),
+                            &achievements,
+                            &mode,
+                            &language,
+                        )
+                        .await
+                        .expect("Failed to write to the database");
                         info!("Got achievements");
                     } else {
                         error!("Failed to fetch achievements");
@@ -247,6 +454,17 @@ async fn main() {
                     } else {
                         error!("Failed to fetch stats")
                     }
+                    match api::gog::library::fetch_owned_products(&token_store, &reqwest_client)
+                        .await
+                    {
+                        Ok(products) => {
+                            db::gameplay::set_owned_products(database.clone(), &products)
+                                .await
+                                .expect("Failed to write to the database");
+                            info!("Got owned products");
+                        }
+                        Err(_) => error!("Failed to fetch owned products"),
+                    }
                 } else {
                     info!("Already in database")
                 }
@@ -292,31 +510,132 @@ async fn main() {
 
                 log::info!("Done");
             }
+            SubCommand::Cloud {
+                command:
+                    CloudCommand::Sync {
+                        client_id,
+                        client_secret,
+                        path,
+                        location,
+                        conflict,
+                    },
+            } => {
+                let new_token = api::gog::users::get_token_for(
+                    &client_id,
+                    &client_secret,
+                    &refresh_token,
+                    &reqwest_client,
+                    false,
+                )
+                .await
+                .expect("Failed to obtain credentials");
+                token_store
+                    .lock()
+                    .await
+                    .insert(client_id.clone(), new_token);
+
+                match comet::cloud::sync(
+                    &token_store,
+                    &client_id,
+                    &galaxy_user_id,
+                    &reqwest_client,
+                    &location,
+                    &path,
+                    conflict,
+                )
+                .await
+                {
+                    Ok(summary) => {
+                        println!("{summary}");
+                        for conflict in &summary.conflicts {
+                            println!("Conflict: {conflict}");
+                        }
+                        if !summary.conflicts.is_empty() {
+                            std::process::exit(2);
+                        }
+                    }
+                    Err(err) => {
+                        error!("Cloud sync failed {err}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            SubCommand::Replay { .. }
+            | SubCommand::Decode { .. }
+            | SubCommand::Components { .. } => unreachable!(),
         }
 
         return;
     }
 
-    let listener = TcpListener::bind("127.0.0.1:9977")
-        .await
-        .expect("Failed to bind to port 9977");
+    let (mut listener, mut configured_address) =
+        match comet::listener::bind(args.listen.as_deref()).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to open the listening socket {err}");
+                std::process::exit(1);
+            }
+        };
+    if args.print_address {
+        match listener.local_addr() {
+            Ok(address) => println!("{address}"),
+            Err(err) => {
+                error!("Failed to read the listening address {err}");
+                std::process::exit(1);
+            }
+        }
+    }
 
     let (topic_sender, _) = tokio::sync::broadcast::channel::<PusherEvent>(20);
     let shutdown_token = tokio_util::sync::CancellationToken::new();
     let pusher_shutdown = shutdown_token.clone(); // Handler for notifications-pusher
     let cloned_shutdown = shutdown_token.clone(); // Handler to share between main thread and sockets
 
+    let overlay_registry = comet::overlay::OverlayRegistry::new();
+
+    let mut presence_receiver = topic_sender.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match presence_receiver.recv().await {
+                Ok(PusherEvent::Topic(message, topic)) if topic == "presence" => {
+                    comet::friends::handle_presence_message(&message)
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    {
+        let token_store = token_store.clone();
+        let user_id = galaxy_user_id.clone();
+        let reqwest_client = reqwest_client.clone();
+        tokio::spawn(async move {
+            set_presence(&token_store, &user_id, Presence::Online, &reqwest_client).await
+        });
+    }
+
     let notifications_pusher_topic_sender = topic_sender.clone();
+    let pusher_recorder = comet::capture::recorder_for(args.record.as_deref(), "pusher").await;
+    let pusher_token_store = token_store.clone();
+    let pusher_client = reqwest_client.clone();
     let pusher_handle = tokio::spawn(async move {
         let mut notification_pusher_client = NotificationPusherClient::new(
             &access_token,
+            pusher_token_store,
+            pusher_client,
             notifications_pusher_topic_sender,
             pusher_shutdown,
+            pusher_recorder,
         )
         .await;
         notification_pusher_client.handle_loop().await;
         warn!("Notification pusher exiting");
     });
+    tokio::spawn(comet::reload::watch_files(cloned_shutdown.clone()));
+    tokio::spawn(comet::lobby::run(
+        galaxy_user_id.parse().expect("Invalid user id"),
+        cloned_shutdown.clone(),
+    ));
     tokio::spawn(async move {
         tokio::signal::ctrl_c()
             .await
@@ -324,20 +643,25 @@ async fn main() {
         shutdown_token.cancel();
     });
 
-    info!("Listening on port 9977");
     let socket_shutdown = cloned_shutdown.clone();
     let cloned_user_info = user_info.clone();
 
     let (client_exit, mut con_exit_recv) = tokio::sync::mpsc::unbounded_channel::<bool>();
-    let (overlay_ipc, _recv) = tokio::sync::broadcast::channel::<(u32, OverlayPeerMessage)>(32);
 
-    let comet_idle_wait: u64 = match std::env::var("COMET_IDLE_WAIT") {
-        Ok(wait) => wait.parse().unwrap_or(15),
-        Err(_) => 15,
+    let connection_options = api::handlers::ConnectionOptions {
+        record_dir: args.record.clone(),
+        proxy: args
+            .proxy
+            .clone()
+            .map(|upstream| api::handlers::proxy::ProxyOptions {
+                upstream,
+                answer_locally: args.proxy_answer_locally,
+            }),
     };
     let mut ever_connected = false;
     let mut active_clients = 0;
     let mut handlers = Vec::new();
+    let mut reloads = comet::reload::subscribe();
     loop {
         let (socket, _addr) = tokio::select! {
             accept = listener.accept() => {
@@ -349,15 +673,24 @@ async fn main() {
                     }
                 }
             }
-            _ = tokio::time::sleep(Duration::from_secs(comet_idle_wait)) => {
+            // idle_wait of 0 quits right after the last game disconnects
+            _ = tokio::time::sleep(Duration::from_secs(comet::CONFIG.get().runtime.idle_wait)), if active_clients == 0 && ever_connected => {
+                socket_shutdown.cancel();
+                break
+            },
+            _ = con_exit_recv.recv() => {
+                active_clients -= 1;
                 handlers.retain(|handler: &JoinHandle<()>| !handler.is_finished());
-                if active_clients == 0 && ever_connected {
-                    socket_shutdown.cancel();
-                    break
+                continue;
+            }
+            Ok(()) = reloads.changed(), if configured_address.is_some() => {
+                let current = configured_address.as_deref().unwrap_or_default();
+                if let Some((rebound, address)) = comet::listener::rebind(current).await {
+                    listener = rebound;
+                    configured_address = Some(address);
                 }
                 continue;
-            },
-            _ = con_exit_recv.recv() => { active_clients -= 1; continue; }
+            }
             _ = socket_shutdown.cancelled() => {break}
         };
 
@@ -369,7 +702,8 @@ async fn main() {
         let shutdown_handler = socket_shutdown.clone();
         let socket_user_info = cloned_user_info.clone();
         let client_exit = client_exit.clone();
-        let achievement_unlock_event = overlay_ipc.clone();
+        let overlay_registry = overlay_registry.clone();
+        let connection_options = connection_options.clone();
         active_clients += 1;
         ever_connected = args.quit;
         handlers.push(tokio::spawn(async move {
@@ -379,8 +713,9 @@ async fn main() {
                 cloned_token_store,
                 socket_user_info,
                 socket_topic_receiver,
-                achievement_unlock_event,
+                overlay_registry,
                 shutdown_handler,
+                connection_options,
             )
             .await;
             let _ = client_exit.send(true);
@@ -390,4 +725,189 @@ async fn main() {
     // Ignore errors, we are exiting
     let _ = pusher_handle.await;
     join_all(handlers).await;
+    api::handlers::discard_pending_presence().await;
+    set_presence(
+        &token_store,
+        &galaxy_user_id,
+        Presence::Offline,
+        &reqwest_client,
+    )
+    .await;
+}
+
+async fn set_presence(
+    token_store: &constants::TokenStorage,
+    user_id: &str,
+    presence: Presence,
+    reqwest_client: &Client,
+) {
+    let request = api::gog::presence::set_presence(token_store, user_id, &presence, reqwest_client);
+    match tokio::time::timeout(Duration::from_secs(10), request).await {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => warn!("Failed to publish presence {:?}", err),
+        Err(_) => warn!("Publishing presence timed out"),
+    }
+}
+
+async fn decode(input: Option<&Path>, hex: Option<&str>, json: bool) -> bool {
+    let format = if json {
+        decode::OutputFormat::Json
+    } else {
+        decode::OutputFormat::Text
+    };
+
+    // Capture files are decoded record by record, anything else is treated as a hex dump
+    let frames: Vec<(String, Vec<u8>)> = match (input, hex) {
+        (Some(path), _) => match comet::capture::read_capture(path).await {
+            Ok(records) => records
+                .iter()
+                .filter_map(|record| {
+                    let label = format!("{:?} {}", record.direction, record.time);
+                    record.frame().ok().map(|frame| (label, frame))
+                })
+                .collect(),
+            Err(_) => match std::fs::read_to_string(path) {
+                Ok(data) => match decode::parse_hex(&data) {
+                    Ok(data) => vec![(String::new(), data)],
+                    Err(err) => {
+                        error!("Failed to parse hex dump {err}");
+                        return false;
+                    }
+                },
+                Err(err) => {
+                    error!("Failed to read {:?} {err}", path);
+                    return false;
+                }
+            },
+        },
+        (None, Some(hex)) => match decode::parse_hex(hex) {
+            Ok(data) => vec![(String::new(), data)],
+            Err(err) => {
+                error!("Failed to parse hex dump {err}");
+                return false;
+            }
+        },
+        (None, None) => {
+            error!("Provide a capture file or --hex");
+            return false;
+        }
+    };
+
+    for (label, data) in frames {
+        let payloads = decode::split_frames(&data);
+        if payloads.is_empty() {
+            warn!("No complete frame found {label}");
+        }
+        for payload in payloads {
+            if !label.is_empty() && format == decode::OutputFormat::Text {
+                println!("# {label}");
+            }
+            println!("{}", decode::decode(&payload, format));
+        }
+    }
+    true
+}
+
+async fn components(command: &ComponentsCommand, reqwest_client: &Client) -> bool {
+    use api::gog::components;
+    let dest_path = paths::REDISTS_STORAGE.as_path();
+    match command {
+        ComponentsCommand::List => {
+            let installed = components::installed_components(dest_path).await;
+            if installed.is_empty() {
+                println!("No components installed in {:?}", dest_path);
+            }
+            for installed in installed {
+                let manifest = &installed.manifest;
+                let time = chrono::DateTime::from_timestamp(manifest.time, 0)
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or_default();
+                let mut line = format!(
+                    "{} ({}) {} checked {}",
+                    installed.component, installed.platform, manifest.version, time
+                );
+                if let Some(pinned) = &manifest.pinned {
+                    line.push_str(&format!(", pinned to {pinned}"));
+                }
+                if let Some(previous) = &installed.previous_version {
+                    line.push_str(&format!(", previous {previous}"));
+                }
+                println!("{line}");
+            }
+            true
+        }
+        ComponentsCommand::Update {
+            component,
+            platform,
+            from,
+        } => {
+            let source = match from {
+                Some(path) => components::ComponentSource::Local(path.clone()),
+                None => components::ComponentSource::from_config(),
+            };
+            let to_update: Vec<(Component, Platform)> = match component {
+                Some(component) => vec![(*component, *platform)],
+                None => components::installed_components(dest_path)
+                    .await
+                    .into_iter()
+                    .map(|installed| (installed.component, installed.platform))
+                    .collect(),
+            };
+            let mut success = true;
+            for (component, platform) in to_update {
+                if let Err(err) = components::update_component(
+                    reqwest_client,
+                    &source,
+                    dest_path.to_path_buf(),
+                    platform,
+                    component,
+                    true,
+                    &components::log_progress,
+                )
+                .await
+                {
+                    error!("Failed to update {component} for {platform} {err}");
+                    success = false;
+                }
+            }
+            success
+        }
+        ComponentsCommand::Remove {
+            component,
+            platform,
+        } => match components::remove_component(dest_path, *platform, *component).await {
+            Ok(true) => {
+                info!("Removed {component} for {platform}");
+                true
+            }
+            Ok(false) => {
+                warn!("{component} for {platform} is not installed");
+                true
+            }
+            Err(err) => {
+                error!("Failed to remove {component} {err}");
+                false
+            }
+        },
+        ComponentsCommand::Pin {
+            component,
+            platform,
+            version,
+            unpin,
+        } => {
+            let result = if *unpin {
+                components::unpin_component(dest_path, *platform, *component)
+                    .await
+                    .map(|_| info!("{component} for {platform} will be updated again"))
+            } else {
+                components::pin_component(dest_path, *platform, *component, version.clone())
+                    .await
+                    .map(|version| info!("Pinned {component} for {platform} to {version}"))
+            };
+            if let Err(err) = &result {
+                error!("{err}");
+            }
+            result.is_ok()
+        }
+    }
 }
@@ -0,0 +1,100 @@
+use crate::api::gog::achievements::Achievement;
+use crate::config::OverlayNotificationConfig;
+use crate::paths;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use zbus::zvariant::Value;
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+async fn cached_icon(
+    reqwest_client: &Client,
+    client_id: &str,
+    achievement: &Achievement,
+) -> Option<PathBuf> {
+    let icon_path = paths::ACHIEVEMENT_ICONS
+        .join(client_id)
+        .join(format!("{}.png", achievement.achievement_id()));
+    if icon_path.exists() {
+        return Some(icon_path);
+    }
+
+    // Notification is shown without icon rather than waiting for a slow image server
+    let response = reqwest_client
+        .get(achievement.image_url_unlocked())
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    let data = match response {
+        Ok(response) => response.bytes().await,
+        Err(err) => Err(err),
+    };
+    let data = data
+        .inspect_err(|err| log::debug!("Failed to download achievement icon {err}"))
+        .ok()?;
+    tokio::fs::create_dir_all(icon_path.parent()?).await.ok()?;
+    tokio::fs::write(&icon_path, data).await.ok()?;
+    Some(icon_path)
+}
+
+/// Show achievement unlock through org.freedesktop.Notifications, used when there is no overlay
+/// attached to the game
+pub async fn achievement_notification(
+    reqwest_client: &Client,
+    client_id: &str,
+    achievement: &Achievement,
+    config: &OverlayNotificationConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let icon = cached_icon(reqwest_client, client_id, achievement)
+        .await
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut hints = HashMap::new();
+    hints.insert("suppress-sound", Value::from(!config.sound));
+    if !icon.is_empty() {
+        hints.insert("image-path", Value::from(icon.as_str()));
+    }
+
+    let connection = zbus::Connection::session().await?;
+    let proxy = NotificationsProxy::new(&connection).await?;
+    proxy
+        .notify(
+            "Comet",
+            0,
+            &icon,
+            &format!("Achievement unlocked: {}", achievement.name()),
+            achievement.description(),
+            &[],
+            hints,
+            -1,
+        )
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::{debug, warn};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::api::gog::overlay::OverlayPeerMessage;
+use crate::api::gog::presence::Presence;
+
+/// Sending ends of a game connection, one for the game socket and one for its overlay pipe
+#[derive(Clone, Debug)]
+pub struct SessionChannels {
+    game: UnboundedSender<OverlayPeerMessage>,
+    overlay: UnboundedSender<OverlayPeerMessage>,
+}
+
+pub struct SessionReceivers {
+    pub game: UnboundedReceiver<OverlayPeerMessage>,
+    pub overlay: UnboundedReceiver<OverlayPeerMessage>,
+}
+
+/// Create channels for a new game connection, they receive messages once the game PID is registered
+pub fn channels() -> (SessionChannels, SessionReceivers) {
+    let (game_sender, game_receiver) = mpsc::unbounded_channel();
+    let (overlay_sender, overlay_receiver) = mpsc::unbounded_channel();
+    (
+        SessionChannels {
+            game: game_sender,
+            overlay: overlay_sender,
+        },
+        SessionReceivers {
+            game: game_receiver,
+            overlay: overlay_receiver,
+        },
+    )
+}
+
+struct Session {
+    client_id: Option<String>,
+    channels: SessionChannels,
+    /// Overlay pipe created for the game
+    listener: Option<String>,
+    /// Last in game presence published for the game
+    presence: Option<(Instant, Presence)>,
+}
+
+impl Session {
+    fn send(&self, msg: OverlayPeerMessage) -> bool {
+        match msg {
+            OverlayPeerMessage::VisibilityChange(_) | OverlayPeerMessage::GameJoin(_) => {
+                self.channels.game.send(msg).is_ok()
+            }
+            OverlayPeerMessage::InitConnection(_)
+            | OverlayPeerMessage::OpenWebPage(_)
+            | OverlayPeerMessage::InvitationDialog(_)
+            | OverlayPeerMessage::DisablePopups(_) => self.channels.overlay.send(msg).is_ok(),
+            // Game connection falls back to desktop notifications when overlay is not attached
+            OverlayPeerMessage::Achievement(_) => {
+                let overlay = self.channels.overlay.send(msg.clone()).is_ok();
+                self.channels.game.send(msg).is_ok() || overlay
+            }
+        }
+    }
+}
+
+/// Games with an active connection keyed by their PID
+#[derive(Clone, Default)]
+pub struct OverlayRegistry {
+    sessions: Arc<Mutex<HashMap<u32, Session>>>,
+}
+
+impl OverlayRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route messages for the game PID to the channels of its connection
+    pub fn register(&self, pid: u32, client_id: Option<&str>, channels: &SessionChannels) {
+        let mut sessions = self.sessions.lock().unwrap();
+        // Overlay may report a different PID than the game did, the connection keeps one entry
+        let previous_pids: Vec<u32> = sessions
+            .iter()
+            .filter(|(session_pid, session)| {
+                **session_pid != pid && session.channels.game.same_channel(&channels.game)
+            })
+            .map(|(session_pid, _)| *session_pid)
+            .collect();
+        let mut moved = None;
+        for previous_pid in previous_pids {
+            debug!("Moving overlay session from {previous_pid} to {pid}");
+            moved = sessions.remove(&previous_pid).or(moved);
+        }
+        let client_id = client_id
+            .map(str::to_owned)
+            .or_else(|| moved.as_ref().and_then(|session| session.client_id.clone()));
+        let (listener, presence) = moved
+            .map(|session| (session.listener, session.presence))
+            .unwrap_or_default();
+        match sessions.get_mut(&pid) {
+            Some(session) if session.channels.game.same_channel(&channels.game) => {
+                if client_id.is_some() {
+                    session.client_id = client_id;
+                }
+            }
+            previous => {
+                if previous.is_some() {
+                    warn!("Game {pid} was registered by another connection, replacing");
+                }
+                debug!("Registering overlay session for {pid}");
+                sessions.insert(
+                    pid,
+                    Session {
+                        client_id,
+                        channels: channels.clone(),
+                        listener,
+                        presence,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Hand overlay pipe of the game over to the overlay thread of its connection
+    pub fn register_listener(&self, pid: u32, listener: String) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(&pid) else {
+            warn!("No session registered for {pid}, not listening for overlay");
+            return false;
+        };
+        session.listener = Some(listener.clone());
+        session.send(OverlayPeerMessage::InitConnection(listener))
+    }
+
+    /// Send message to the game with PID, returns false when it's not connected
+    pub fn send(&self, pid: u32, msg: OverlayPeerMessage) -> bool {
+        match self.sessions.lock().unwrap().get(&pid) {
+            Some(session) => session.send(msg),
+            None => {
+                debug!("Dropping overlay message for unknown game {pid}");
+                false
+            }
+        }
+    }
+
+    /// Send message to every running game of client_id, returns number of games reached
+    pub fn send_to_client(&self, client_id: &str, msg: OverlayPeerMessage) -> usize {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|session| session.client_id.as_deref() == Some(client_id))
+            .filter(|session| session.send(msg.clone()))
+            .count()
+    }
+
+    /// Remember presence published for the game of the connection
+    pub fn set_presence(&self, channels: &SessionChannels, presence: Presence) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions
+            .values_mut()
+            .find(|session| session.channels.game.same_channel(&channels.game))
+        {
+            session.presence = Some((Instant::now(), presence));
+        }
+    }
+
+    /// Most recently published presence of the games still running
+    pub fn latest_presence(&self) -> Option<Presence> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|session| session.presence.as_ref())
+            .max_by_key(|(updated, _)| *updated)
+            .map(|(_, presence)| presence.clone())
+    }
+
+    /// Forget the game and remove its overlay pipe
+    pub fn unregister(&self, pid: u32, channels: &SessionChannels) {
+        let mut sessions = self.sessions.lock().unwrap();
+        // The PID may have been taken over by a newer connection in the meantime
+        if !sessions
+            .get(&pid)
+            .is_some_and(|session| session.channels.game.same_channel(&channels.game))
+        {
+            return;
+        }
+        let listener = sessions.remove(&pid).and_then(|session| session.listener);
+        debug!("Removed overlay session for {pid}");
+        // Named pipes are gone once closed, unix sockets stay on disk
+        if cfg!(unix)
+            && let Some(listener) = listener
+        {
+            let _ = std::fs::remove_file(listener);
+        }
+    }
+}
@@ -40,5 +40,9 @@ lazy_static! {
     pub static ref GAMEPLAY_STORAGE: PathBuf = DATA_PATH.join("gameplay");
     pub static ref REDISTS_STORAGE: PathBuf = DATA_PATH.join("redist");
     pub static ref WORKAROUNDS: PathBuf = DATA_PATH.join("workarounds");
+    pub static ref ACHIEVEMENT_ICONS: PathBuf = DATA_PATH.join("cache/achievements");
+    pub static ref FRIENDS_CACHE: PathBuf = DATA_PATH.join("cache/friends");
+    pub static ref PRODUCTS_CACHE: PathBuf = DATA_PATH.join("cache/products");
+    pub static ref KNOWN_PRODUCTS: PathBuf = DATA_PATH.join("cache/product_ids.json");
     pub static ref CONFIG_FILE: PathBuf = CONFIG_PATH.join("config.toml");
 }
@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::prelude::*;
+use futures::StreamExt;
+use log::{debug, warn};
+use reqwest::Client;
+use serde_json::Value;
+
+use crate::api::gog::products;
+use crate::paths;
+
+// Overlay asks for many products at once, don't flood the API
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+
+lazy_static! {
+    // Products whose cache is being written by a detached task
+    static ref WRITING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+fn product_dir(product_id: &str) -> PathBuf {
+    paths::PRODUCTS_CACHE.join(product_id)
+}
+
+// Cached image file is named after its key in `images` and keeps extension of the url
+fn image_name(key: &str, url: &str) -> String {
+    let extension = url
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.split('?').next())
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension)
+        .unwrap_or("png");
+    format!("{key}.{extension}")
+}
+
+fn mime_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        _ => "image/png",
+    }
+}
+
+fn image_urls(product: &Value) -> Vec<(String, String)> {
+    let Some(images) = product.get("images").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    images
+        .iter()
+        .filter_map(|(key, url)| {
+            url.as_str()
+                .filter(|url| url.starts_with("https://"))
+                .map(|url| (key.clone(), url.to_owned()))
+        })
+        .collect()
+}
+
+/// Cached product details and whether they are still fresh
+async fn read_cache(product_id: &str) -> Option<(Value, bool)> {
+    let path = product_dir(product_id).join("product.json");
+    let metadata = tokio::fs::metadata(&path).await.ok()?;
+    let data = tokio::fs::read(&path).await.ok()?;
+    let product = serde_json::from_slice(&data)
+        .inspect_err(|err| warn!("Invalid product cache of {product_id} {err}"))
+        .ok()?;
+    let ttl = Duration::from_secs(crate::CONFIG.get().runtime.product_cache_ttl);
+    let fresh = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < ttl);
+    Some((product, fresh))
+}
+
+// Readers never see a partially written file
+async fn write_file(path: &Path, data: Vec<u8>) -> std::io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    match tokio::fs::write(&temp_path, data).await {
+        Ok(()) => tokio::fs::rename(&temp_path, path).await,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            Err(err)
+        }
+    }
+}
+
+async fn write_cache(product_id: String, product: Value, reqwest_client: Client) {
+    if !WRITING.lock().unwrap().insert(product_id.clone()) {
+        debug!("Product cache of {product_id} is already being written");
+        return;
+    }
+    write_product(&product_id, &product, &reqwest_client).await;
+    WRITING.lock().unwrap().remove(&product_id);
+}
+
+async fn write_product(product_id: &str, product: &Value, reqwest_client: &Client) {
+    let dir = product_dir(product_id);
+    if let Err(err) = tokio::fs::create_dir_all(&dir).await {
+        warn!("Failed to create product cache directory {err}");
+        return;
+    }
+    let downloads: Vec<_> = image_urls(product)
+        .into_iter()
+        .map(|(key, url)| {
+            let path = dir.join(image_name(&key, &url));
+            async move {
+                match products::fetch_image(&url, reqwest_client).await {
+                    Ok(data) => {
+                        if let Err(err) = write_file(&path, data).await {
+                            warn!("Failed to cache image {url} {err}");
+                        }
+                    }
+                    Err(err) => debug!("Failed to download image {url} {:?}", err),
+                }
+            }
+        })
+        .collect();
+    futures::stream::iter(downloads)
+        .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+        .collect::<Vec<()>>()
+        .await;
+
+    let data = serde_json::to_vec(product).unwrap();
+    if let Err(err) = write_file(&dir.join("product.json"), data).await {
+        warn!("Failed to write product cache {err}");
+    }
+}
+
+// Image urls are replaced with cached images, so the art is shown without network
+async fn embed_images(product_id: &str, mut product: Value) -> Value {
+    let dir = product_dir(product_id);
+    if let Some(Value::Object(images)) = product.get_mut("images") {
+        for (key, value) in images.iter_mut() {
+            let Value::String(url) = value else { continue };
+            let name = image_name(key, url);
+            if let Ok(data) = tokio::fs::read(dir.join(&name)).await {
+                *value = Value::String(format!(
+                    "data:{};base64,{}",
+                    mime_type(&name),
+                    BASE64_STANDARD.encode(data)
+                ));
+            }
+        }
+    }
+    product
+}
+
+/// Product details shown in the overlay, kept on disk for `runtime.product_cache_ttl`.
+/// When offline or the request fails, cached details are used even when expired
+pub async fn product_details(
+    product_id: &str,
+    reqwest_client: &Client,
+    online: bool,
+) -> Option<Value> {
+    if product_id.is_empty() || !product_id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let cached = read_cache(product_id).await;
+    if !online {
+        let (product, _) = cached?;
+        return Some(embed_images(product_id, product).await);
+    }
+    let cached = match cached {
+        Some((product, true)) => return Some(product),
+        cached => cached,
+    };
+
+    match products::fetch_product(product_id, reqwest_client).await {
+        Ok(product) => {
+            tokio::spawn(write_cache(
+                product_id.to_owned(),
+                product.clone(),
+                reqwest_client.clone(),
+            ));
+            Some(product)
+        }
+        Err(err) => {
+            warn!(
+                "Failed to fetch product {product_id}, using cache {:?}",
+                err
+            );
+            let (product, _) = cached?;
+            Some(embed_images(product_id, product).await)
+        }
+    }
+}
+
+/// Details of multiple products fetched in parallel, unknown products are skipped
+pub async fn products_details(
+    product_ids: &[String],
+    reqwest_client: &Client,
+    online: bool,
+) -> Vec<Value> {
+    let mut seen = HashSet::new();
+    let requests: Vec<_> = product_ids
+        .iter()
+        .filter(|id| seen.insert(id.as_str()))
+        .map(|id| product_details(id, reqwest_client, online))
+        .collect();
+    let products: Vec<Option<Value>> = futures::stream::iter(requests)
+        .buffered(MAX_CONCURRENT_REQUESTS)
+        .collect()
+        .await;
+    products.into_iter().flatten().collect()
+}
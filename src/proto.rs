@@ -2,8 +2,46 @@
 include!(concat!(env!("OUT_DIR"), "/proto/mod.rs"));
 
 pub mod common_utils {
+    use protobuf::Message;
+
     pub struct ProtoPayload {
         pub header: super::gog_protocols_pb::Header,
         pub payload: Vec<u8>,
     }
+
+    impl ProtoPayload {
+        /// Parse single frame from the start of the buffer
+        pub fn from_frame(data: &[u8]) -> Option<Self> {
+            let header_size: usize = u16::from_be_bytes([*data.first()?, *data.get(1)?]).into();
+            let header =
+                super::gog_protocols_pb::Header::parse_from_bytes(data.get(2..2 + header_size)?)
+                    .ok()?;
+            let payload_start = 2 + header_size;
+            let payload_size: usize = header.size().try_into().ok()?;
+            let payload = data
+                .get(payload_start..payload_start + payload_size)?
+                .to_vec();
+            Some(Self { header, payload })
+        }
+
+        /// oseq of the request this frame responds to, stored in the header field 100
+        pub fn response_oseq(&self) -> Option<u32> {
+            match self.header.special_fields.unknown_fields().get(100) {
+                Some(protobuf::UnknownValueRef::Varint(oseq)) => oseq.try_into().ok(),
+                _ => None,
+            }
+        }
+
+        /// Encode to the wire format - header size, header and payload
+        pub fn frame(&self) -> Result<Vec<u8>, protobuf::Error> {
+            let header_buffer = self.header.write_to_bytes()?;
+            let header_size: u16 = header_buffer.len().try_into().unwrap();
+
+            let mut buffer = Vec::with_capacity(2 + header_buffer.len() + self.payload.len());
+            buffer.extend(header_size.to_be_bytes());
+            buffer.extend(header_buffer);
+            buffer.extend(&self.payload);
+            Ok(buffer)
+        }
+    }
 }
@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::{error, info};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::paths;
+
+lazy_static! {
+    static ref RELOADS: watch::Sender<u64> = watch::channel(0).0;
+}
+
+/// Receiver notified every time configuration and workarounds are reloaded
+pub fn subscribe() -> watch::Receiver<u64> {
+    RELOADS.subscribe()
+}
+
+/// Reload the configuration file and notify running sessions to reload their workarounds
+pub fn reload() {
+    match crate::CONFIG.reload() {
+        Ok(()) => info!("Configuration reloaded"),
+        Err(err) => error!("Failed to reload configuration, keeping the previous one {err}"),
+    }
+    RELOADS.send_modify(|generation| *generation += 1);
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+// Modification times of the config file and every workaround file
+fn snapshot() -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files = vec![(paths::CONFIG_FILE.clone(), modified(&paths::CONFIG_FILE))];
+    if let Ok(entries) = std::fs::read_dir(paths::WORKAROUNDS.as_path()) {
+        let mut workarounds: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        workarounds.sort();
+        files.extend(workarounds.into_iter().map(|path| {
+            let time = modified(&path);
+            (path, time)
+        }));
+    }
+    files
+}
+
+/// Reload when the config file or workarounds directory changes, or on SIGHUP
+pub async fn watch_files(shutdown_token: CancellationToken) {
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => Some(signal),
+        Err(err) => {
+            error!("Failed to listen to SIGHUP {err}");
+            None
+        }
+    };
+
+    let mut last_snapshot = snapshot();
+    loop {
+        #[cfg(unix)]
+        let hangup_received = async {
+            match hangup.as_mut() {
+                Some(signal) => signal.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hangup_received = std::future::pending::<Option<()>>();
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(2)) => {
+                let current = match tokio::task::spawn_blocking(snapshot).await {
+                    Ok(current) => current,
+                    Err(err) => {
+                        error!("Failed to check configuration files {err}");
+                        continue;
+                    }
+                };
+                if current != last_snapshot {
+                    last_snapshot = current;
+                    reload();
+                }
+            }
+            _ = hangup_received => {
+                info!("Received SIGHUP");
+                last_snapshot = snapshot();
+                reload();
+            }
+            _ = shutdown_token.cancelled() => break
+        }
+    }
+}
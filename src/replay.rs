@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use comet::capture::{self, Direction};
+use comet::proto::common_utils::ProtoPayload;
+use comet::proto::gog_protocols_pb::Header;
+use log::{debug, error, info, warn};
+use protobuf::Message;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+async fn read_frame(socket: &mut TcpStream) -> Result<Vec<u8>, tokio::io::Error> {
+    let header_size = socket.read_u16().await?;
+    let mut header_buffer = vec![0; header_size.into()];
+    socket.read_exact(&mut header_buffer).await?;
+    let header = Header::parse_from_bytes(&header_buffer)?;
+
+    let mut payload = vec![0; header.size().try_into().unwrap()];
+    socket.read_exact(&mut payload).await?;
+
+    let mut frame = header_size.to_be_bytes().to_vec();
+    frame.extend(header_buffer);
+    frame.extend(payload);
+    Ok(frame)
+}
+
+fn compare(index: usize, expected: &[u8], received: &[u8]) -> bool {
+    if expected == received {
+        return true;
+    }
+    let (Some(expected), Some(received)) = (
+        ProtoPayload::from_frame(expected),
+        ProtoPayload::from_frame(received),
+    ) else {
+        println!("#{index}: frames differ and could not be parsed");
+        return false;
+    };
+    let header_pairs = [
+        ("sort", expected.header.sort(), received.header.sort()),
+        ("type", expected.header.type_(), received.header.type_()),
+        ("size", expected.header.size(), received.header.size()),
+    ];
+    let mut differences: Vec<String> = header_pairs
+        .iter()
+        .filter(|(_, expected, received)| expected != received)
+        .map(|(name, expected, received)| format!("{name} {expected} != {received}"))
+        .collect();
+    if expected.payload != received.payload {
+        differences.push(String::from("payload differs"));
+    }
+    if differences.is_empty() {
+        // Only the header extensions (oseq, status) differ
+        differences.push(String::from("header fields differ"));
+    }
+    println!(
+        "#{index} {}: {}",
+        comet::decode::describe(&expected),
+        differences.join(", ")
+    );
+    false
+}
+
+// Start comet listening on a port picked by the system, it's killed once the replay is done
+async fn start_instance(instance_args: &[String]) -> Result<(Child, String), tokio::io::Error> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(instance_args)
+        .args(["--listen", "127.0.0.1:0", "--print-address"])
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut lines = BufReader::new(stdout).lines();
+    let line = tokio::time::timeout(Duration::from_secs(10), lines.next_line())
+        .await
+        .map_err(|_| tokio::io::Error::other("comet didn't start listening in time"))??;
+    let Some(address) = line else {
+        let status = child.wait().await?;
+        return Err(tokio::io::Error::other(format!(
+            "comet exited with {status}"
+        )));
+    };
+    // Keep reading, so the instance doesn't fail writing to a closed pipe
+    tokio::spawn(async move { while let Ok(Some(_)) = lines.next_line().await {} });
+    Ok((child, address))
+}
+
+// Read frames until the response to oseq arrives, responses to other requests are kept for later.
+// Returns None when no response arrived in time
+async fn wait_for_response(
+    socket: &mut TcpStream,
+    oseq: u32,
+    received: &mut HashMap<u32, Vec<u8>>,
+) -> Result<Option<Vec<u8>>, tokio::io::Error> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Ok(frame) = tokio::time::timeout(remaining, read_frame(socket)).await else {
+            return Ok(None);
+        };
+        let frame = frame?;
+        match ProtoPayload::from_frame(&frame).and_then(|payload| payload.response_oseq()) {
+            Some(response_oseq) if response_oseq == oseq => return Ok(Some(frame)),
+            Some(response_oseq) => {
+                received.insert(response_oseq, frame);
+            }
+            None => debug!("Ignoring frame sent without a request"),
+        }
+    }
+}
+
+fn request_oseq(frame: &[u8]) -> Option<u32> {
+    ProtoPayload::from_frame(frame)
+        .filter(|payload| payload.header.has_oseq())
+        .map(|payload| payload.header.oseq())
+}
+
+/// Send every incoming frame of the capture to comet and compare its responses with the
+/// recorded ones. Without address a new comet instance is started with instance_args.
+/// Returns true when all responses matched
+pub async fn replay(capture_path: &Path, address: Option<&str>, instance_args: &[String]) -> bool {
+    let records = match capture::read_capture(capture_path).await {
+        Ok(records) => records,
+        Err(err) => {
+            error!("Failed to read capture {:?} {err}", capture_path);
+            return false;
+        }
+    };
+
+    let (_instance, address) = match address {
+        Some(address) => (None, address.to_owned()),
+        None => match start_instance(instance_args).await {
+            Ok((child, address)) => (Some(child), address),
+            Err(err) => {
+                error!("Failed to start comet {err}");
+                return false;
+            }
+        },
+    };
+    let mut socket = match TcpStream::connect(&address).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("Failed to connect to {address} {err}");
+            return false;
+        }
+    };
+    info!("Replaying {} frames to {address}", records.len());
+
+    // Responses are matched with requests by oseq, frames sent without a request
+    // (pusher topics, overlay notifications) are not compared
+    let mut expected: HashMap<u32, (usize, Vec<u8>)> = HashMap::new();
+    let mut unsolicited = 0;
+    for (index, record) in records.iter().enumerate() {
+        if record.direction != Direction::Out {
+            continue;
+        }
+        let Ok(frame) = record.frame() else {
+            warn!("Skipping malformed frame #{index}");
+            continue;
+        };
+        match ProtoPayload::from_frame(&frame).and_then(|payload| payload.response_oseq()) {
+            Some(oseq) => {
+                expected.insert(oseq, (index, frame));
+            }
+            None => unsolicited += 1,
+        }
+    }
+
+    let mut received = HashMap::new();
+    let mut matched = 0;
+    let mut mismatched = 0;
+    for (index, record) in records.iter().enumerate() {
+        if record.direction != Direction::In {
+            continue;
+        }
+        let Ok(frame) = record.frame() else {
+            warn!("Skipping malformed frame #{index}");
+            continue;
+        };
+        if let Err(err) = socket.write_all(&frame).await {
+            error!("Failed to send frame #{index} {err}");
+            return false;
+        }
+        let Some(oseq) = request_oseq(&frame) else {
+            continue;
+        };
+        let Some((expected_index, expected_frame)) = expected.remove(&oseq) else {
+            continue;
+        };
+        let response = match received.remove(&oseq) {
+            Some(response) => Some(response),
+            None => match wait_for_response(&mut socket, oseq, &mut received).await {
+                Ok(response) => response,
+                Err(err) => {
+                    error!("Failed to read response for #{expected_index} {err}");
+                    return false;
+                }
+            },
+        };
+        match response {
+            Some(response) if compare(expected_index, &expected_frame, &response) => matched += 1,
+            Some(_) => mismatched += 1,
+            None => {
+                println!("#{expected_index}: no response received");
+                mismatched += 1;
+            }
+        }
+    }
+
+    println!(
+        "Matched: {matched}, mismatched: {mismatched}, skipped frames sent without request: {unsolicited}"
+    );
+    mismatched == 0
+}